@@ -0,0 +1,416 @@
+//! Async counterparts of the ingest methods in `main.rs`, built on tokio-postgres.
+//!
+//! Every worker is a tokio task with its own connection, so `--concurrency` can go to
+//! hundreds of connections while the runtime only uses one OS thread per core.
+
+use std::error::Error;
+use std::pin::pin;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures_util::stream::FuturesOrdered;
+use futures_util::{SinkExt, StreamExt};
+use tokio::runtime::Runtime;
+use tokio::sync::Barrier;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::Type;
+use tokio_postgres::Client;
+
+use crate::batches::{Batches, WorkerInput};
+use crate::connection::ConnectionInfo;
+use crate::row::{self, Row};
+use crate::scenario;
+use crate::schema::TableSchema;
+use crate::stats::LatencyHistogram;
+use crate::tls::Connector;
+use crate::{
+    create_benchmark_result, unnest_params, values_params,
+    BenchmarkResult, IngestContext, IngestMethod, StageTimes, PIPELINE_DEPTH,
+};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+pub async fn connect(connector: &Connector, conn_info: &ConnectionInfo) -> Result<Client, BoxError> {
+    Ok(connector.connect_async(&conn_info.connection_string).await?)
+}
+
+/// Sets the session settings of the benchmark on a worker connection
+pub async fn apply_settings(client: &Client, settings: &[(String, String)]) -> Result<(), BoxError> {
+    if let Some((query, params)) = scenario::set_config_query(settings) {
+        client.execute(&query, &params).await.map_err(|e| scenario::settings_error(settings, e))?;
+    }
+    Ok(())
+}
+
+/// Runs one task per worker input, each ingesting over its own connection. The tasks
+/// connect first and start together once all are connected; the table is expected to
+/// have been truncated by the caller. Returns the task results and the moment they started.
+pub fn run(
+    runtime: &Runtime,
+    connector: &Connector,
+    method: IngestMethod,
+    inputs: Vec<WorkerInput>,
+    ctx: &IngestContext,
+) -> Result<(Vec<BenchmarkResult>, Instant), BoxError> {
+    runtime.block_on(async {
+        let barrier = Arc::new(Barrier::new(inputs.len()));
+        let released = Arc::new(OnceLock::new());
+        let tasks: Vec<_> = inputs
+            .into_iter()
+            .map(|input| {
+                let connector = connector.clone();
+                let ctx = ctx.clone();
+                let barrier = Arc::clone(&barrier);
+                let released = Arc::clone(&released);
+                tokio::spawn(async move {
+                    // Wait even if the connection failed, so the other tasks aren't stuck
+                    let connected = match connect(&connector, &ctx.conn_info).await {
+                        Ok(client) => apply_settings(&client, &ctx.settings).await.map(|_| client),
+                        Err(e) => Err(e),
+                    };
+                    if barrier.wait().await.is_leader() {
+                        let _ = released.set(Instant::now());
+                    }
+                    let client = connected?;
+                    ingest(method, &client, &mut input.batches(ctx.batch_size), &ctx).await
+                })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            results.push(task.await??);
+        }
+        let released = *released.get().expect("tasks passed the start barrier");
+        Ok((results, released))
+    })
+}
+
+pub async fn ingest(
+    method: IngestMethod,
+    client: &Client,
+    batches: &mut Batches<'_>,
+    ctx: &IngestContext,
+) -> Result<BenchmarkResult, BoxError> {
+    match method {
+        IngestMethod::BinaryCopy => binary_copy(client, batches, ctx).await,
+        IngestMethod::InsertValues | IngestMethod::InsertValuesDoNothing | IngestMethod::InsertValuesDoUpdate => {
+            insert_values(client, method, batches, ctx).await
+        }
+        IngestMethod::PreparedInsertValues => prepared_insert_values(client, batches, ctx).await,
+        IngestMethod::InsertUnnest
+        | IngestMethod::InsertUnnestDoNothing
+        | IngestMethod::InsertUnnestDoUpdate
+        | IngestMethod::Merge => insert_unnest(client, method, batches, ctx).await,
+        IngestMethod::PreparedInsertUnnest => prepared_insert_unnest(client, batches, ctx).await,
+        IngestMethod::Copy => copy(client, batches, ctx).await,
+        IngestMethod::StagedCopy
+        | IngestMethod::StagedBinaryCopy
+        | IngestMethod::StagedCopyDoNothing
+        | IngestMethod::StagedCopyDoUpdate
+        | IngestMethod::StagedBinaryCopyDoNothing
+        | IngestMethod::StagedBinaryCopyDoUpdate => staged_copy(client, method, batches, ctx).await,
+        IngestMethod::CopyFreeze | IngestMethod::BinaryCopyFreeze => copy_freeze(client, method, batches, ctx).await,
+        IngestMethod::PipelinedInsertValues => pipelined_insert_values(client, batches, ctx).await,
+        IngestMethod::PipelinedInsertUnnest => pipelined_insert_unnest(client, batches, ctx).await,
+    }
+}
+
+async fn insert_unnest(client: &Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let stmt = ctx.unnest_sql(method);
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        let columns = row::columns_of(&chunk, ctx.schema.columns.len());
+        client.execute(&stmt, &unnest_params(&columns)).await?;
+        latency.record(batch_start.elapsed());
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, method.name(), duration, batches.rows(), latency))
+}
+
+async fn prepared_insert_unnest(client: &Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+    let stmt = client.prepare(&ctx.schema.unnest_sql()).await?;
+
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        let columns = row::columns_of(&chunk, ctx.schema.columns.len());
+        client.execute(&stmt, &unnest_params(&columns)).await?;
+        latency.record(batch_start.elapsed());
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, "Prepared Insert UNNEST", duration, batches.rows(), latency))
+}
+
+async fn insert_values(client: &Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let query = ctx.insert_values_sql(method, ctx.batch_size);
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        if chunk.len() == ctx.batch_size {
+            client.execute(&query, &values_params(&chunk)).await?;
+        } else {
+            client.execute(&ctx.insert_values_sql(method, chunk.len()), &values_params(&chunk)).await?;
+        }
+        latency.record(batch_start.elapsed());
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, method.name(), duration, batches.rows(), latency))
+}
+
+async fn prepared_insert_values(client: &Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let stmt = client.prepare(&ctx.schema.insert_values_sql(ctx.batch_size)).await?;
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        if chunk.len() == ctx.batch_size {
+            client.execute(&stmt, &values_params(&chunk)).await?;
+        } else {
+            let remainder_stmt = client.prepare(&ctx.schema.insert_values_sql(chunk.len())).await?;
+            client.execute(&remainder_stmt, &values_params(&chunk)).await?;
+        }
+        latency.record(batch_start.elapsed());
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, "Prepared Insert VALUES", duration, batches.rows(), latency))
+}
+
+async fn copy(client: &Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let copy_sql = ctx.schema.copy_sql();
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, None, &chunk).await?;
+        latency.record(batch_start.elapsed());
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, "Copy", duration, batches.rows(), latency))
+}
+
+async fn binary_copy(client: &Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let types = ctx.schema.types();
+    let copy_sql = ctx.schema.binary_copy_sql();
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, Some(&types), &chunk).await?;
+        latency.record(batch_start.elapsed());
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, "Binary Copy", duration, batches.rows(), latency))
+}
+
+async fn staged_copy(client: &Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    let mut stages = StageTimes::default();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let pid: i32 = client.query_one("SELECT pg_backend_pid()", &[]).await?.get(0);
+    let name = format!("pgingester_staging_{}", pid);
+    let staging = TableSchema { table: name.clone(), name, columns: ctx.schema.columns.clone() };
+    client.batch_execute(&ctx.schema.create_staging_sql(&staging.table, ctx.staging_table.keyword())).await?;
+    let move_sql = ctx.move_staged_sql(method, &staging.table);
+    let truncate_sql = format!("TRUNCATE {}", staging.table);
+    let binary = matches!(
+        method,
+        IngestMethod::StagedBinaryCopy | IngestMethod::StagedBinaryCopyDoNothing | IngestMethod::StagedBinaryCopyDoUpdate
+    );
+    let types = staging.types();
+    let copy_sql = if binary { staging.binary_copy_sql() } else { staging.copy_sql() };
+
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, binary.then_some(types.as_slice()), &chunk).await?;
+        let copied = std::time::Instant::now();
+        client.execute(&move_sql, &[]).await?;
+        client.batch_execute(&truncate_sql).await?;
+        stages.copy += copied - batch_start;
+        stages.insert += copied.elapsed();
+        latency.record(batch_start.elapsed());
+    }
+
+    client.batch_execute(&format!("DROP TABLE {}", staging.table)).await?;
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    let mut result = create_benchmark_result(ctx, &ctx.method_name(method), duration, batches.rows(), latency);
+    result.stages = Some(stages);
+    Ok(result)
+}
+
+async fn copy_freeze(client: &Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    client.simple_query("BEGIN").await?;
+    client.simple_query(&format!("TRUNCATE TABLE {}", ctx.schema.table)).await?;
+
+    let types = ctx.schema.types();
+    let binary = method == IngestMethod::BinaryCopyFreeze;
+    let copy_sql = if binary { ctx.schema.binary_copy_freeze_sql() } else { ctx.schema.copy_freeze_sql() };
+    while let Some(chunk) = batches.next().await {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, binary.then_some(types.as_slice()), &chunk).await?;
+        latency.record(batch_start.elapsed());
+    }
+
+    client.simple_query("COMMIT").await?;
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, method.name(), duration, batches.rows(), latency))
+}
+
+// One COPY of `chunk`, in binary format if the column `types` are given
+async fn copy_chunk(client: &Client, copy_sql: &str, types: Option<&[Type]>, chunk: &[Row]) -> Result<(), BoxError> {
+    match types {
+        Some(types) => {
+            let sink = client.copy_in(copy_sql).await?;
+            let mut writer = pin!(BinaryCopyInWriter::new(sink, types));
+            for record in chunk {
+                writer.as_mut().write_raw(record.iter()).await?;
+            }
+            writer.finish().await?;
+        }
+        None => {
+            let mut buffer = String::new();
+            for record in chunk {
+                row::write_copy_line(record, &mut buffer);
+            }
+            let mut sink = pin!(client.copy_in::<_, Bytes>(copy_sql).await?);
+            sink.send(Bytes::from(buffer)).await?;
+            sink.finish().await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn pipelined_insert_values(client: &Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+
+    let stmt = client.prepare(&ctx.schema.insert_values_sql(ctx.batch_size)).await?;
+
+    let mut in_flight = FuturesOrdered::new();
+    while let Some(chunk) = batches.next().await {
+        // Only the last batch is short; it gets a statement of its own
+        let stmt = if chunk.len() == ctx.batch_size {
+            stmt.clone()
+        } else {
+            client.prepare(&ctx.schema.insert_values_sql(chunk.len())).await?
+        };
+        if in_flight.len() >= PIPELINE_DEPTH {
+            if let Some(batch_latency) = in_flight.next().await {
+                latency.record(batch_latency?);
+            }
+        }
+        in_flight.push_back(async move {
+            // Timed from the first poll, which is when the statement is sent
+            let batch_start = std::time::Instant::now();
+            client.execute(&stmt, &values_params(&chunk)).await?;
+            Ok::<_, BoxError>(batch_start.elapsed())
+        });
+    }
+    while let Some(batch_latency) = in_flight.next().await {
+        latency.record(batch_latency?);
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, "Pipelined Insert VALUES", duration, batches.rows(), latency))
+}
+
+pub async fn pipelined_insert_unnest(client: &Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, BoxError> {
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN").await?;
+    }
+    let stmt = client.prepare(&ctx.schema.unnest_sql()).await?;
+
+    let mut in_flight = FuturesOrdered::new();
+    while let Some(chunk) = batches.next().await {
+        if in_flight.len() >= PIPELINE_DEPTH {
+            if let Some(batch_latency) = in_flight.next().await {
+                latency.record(batch_latency?);
+            }
+        }
+        let stmt = stmt.clone();
+        in_flight.push_back(async move {
+            let batch_start = std::time::Instant::now();
+            let columns = row::columns_of(&chunk, ctx.schema.columns.len());
+            client.execute(&stmt, &unnest_params(&columns)).await?;
+            Ok::<_, BoxError>(batch_start.elapsed())
+        });
+    }
+    while let Some(batch_latency) = in_flight.next().await {
+        latency.record(batch_latency?);
+    }
+
+    if ctx.transactions {
+        client.simple_query("COMMIT").await?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, "Pipelined Insert UNNEST", duration, batches.rows(), latency))
+}
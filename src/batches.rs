@@ -0,0 +1,115 @@
+//! Where ingest workers get their batches from.
+//!
+//! By default every worker walks its own slice of the preloaded input. With
+//! `--streaming` a reader thread parses the input while the benchmark runs and hands
+//! full batches to the workers through one bounded channel, so memory use stays at
+//! roughly `--concurrency * --stream-buffer` batches regardless of the input size.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::ops::Range;
+use std::slice::Chunks;
+use std::sync::Arc;
+
+use async_channel::{Receiver, Sender};
+
+use crate::row::Row;
+
+pub enum WorkerInput {
+    /// A share of the rows loaded before the benchmark
+    Preloaded(Arc<[Row]>, Range<usize>),
+    /// Batches read from the channel fed by [`feed`]
+    Streamed(Receiver<Vec<Row>>),
+}
+
+impl WorkerInput {
+    pub fn batches(&self, batch_size: usize) -> Batches<'_> {
+        let source = match self {
+            WorkerInput::Preloaded(records, range) => Source::Preloaded(records[range.clone()].chunks(batch_size)),
+            WorkerInput::Streamed(receiver) => Source::Streamed(receiver),
+        };
+        Batches { source, rows: 0 }
+    }
+}
+
+/// Splits `records` into `workers` contiguous shares, the last one taking the remainder
+pub fn preloaded(records: &Arc<[Row]>, workers: usize) -> Vec<WorkerInput> {
+    let chunk_size = records.len() / workers;
+    (0..workers)
+        .map(|i| {
+            let start_idx = i * chunk_size;
+            let end_idx = if i == workers - 1 { records.len() } else { (i + 1) * chunk_size };
+            WorkerInput::Preloaded(Arc::clone(records), start_idx..end_idx)
+        })
+        .collect()
+}
+
+/// One channel shared by `workers` consumers, holding up to `buffer` batches per worker
+pub fn streamed(workers: usize, buffer: usize) -> (Sender<Vec<Row>>, Vec<WorkerInput>) {
+    let (sender, receiver) = async_channel::bounded(workers * buffer.max(1));
+    let inputs = (0..workers).map(|_| WorkerInput::Streamed(receiver.clone())).collect();
+    (sender, inputs)
+}
+
+/// Reader side of [`streamed`]: groups `rows` into batches of `batch_size` and sends them
+/// until the input is exhausted. Dropping `sender` at the end tells the workers to stop.
+pub fn feed<I>(rows: I, batch_size: usize, sender: Sender<Vec<Row>>) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    I: Iterator<Item = Result<Row, Box<dyn Error + Send + Sync>>>,
+{
+    let mut batch = Vec::with_capacity(batch_size);
+    for row in rows {
+        batch.push(row?);
+        if batch.len() == batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            sender.send_blocking(full).map_err(|_| "All ingest workers stopped before the input was read")?;
+        }
+    }
+    if !batch.is_empty() {
+        sender.send_blocking(batch).map_err(|_| "All ingest workers stopped before the input was read")?;
+    }
+    Ok(())
+}
+
+enum Source<'a> {
+    Preloaded(Chunks<'a, Row>),
+    Streamed(&'a Receiver<Vec<Row>>),
+}
+
+/// Batches for one worker, counting the rows handed out
+pub struct Batches<'a> {
+    source: Source<'a>,
+    rows: usize,
+}
+
+impl<'a> Batches<'a> {
+    /// Next batch for a blocking worker
+    pub fn next_blocking(&mut self) -> Option<Cow<'a, [Row]>> {
+        let batch = match &mut self.source {
+            Source::Preloaded(chunks) => chunks.next().map(Cow::Borrowed),
+            Source::Streamed(receiver) => receiver.recv_blocking().ok().map(Cow::Owned),
+        };
+        self.count(batch)
+    }
+
+    /// Next batch for an async worker
+    pub async fn next(&mut self) -> Option<Cow<'a, [Row]>> {
+        let batch = match &mut self.source {
+            Source::Preloaded(chunks) => chunks.next().map(Cow::Borrowed),
+            Source::Streamed(receiver) => receiver.recv().await.ok().map(Cow::Owned),
+        };
+        self.count(batch)
+    }
+
+    fn count(&mut self, batch: Option<Cow<'a, [Row]>>) -> Option<Cow<'a, [Row]>> {
+        if let Some(batch) = &batch {
+            self.rows += batch.len();
+        }
+        batch
+    }
+
+    /// Rows handed out so far
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+}
@@ -0,0 +1,265 @@
+//! `pgingester compare`: matches the results of two runs and flags throughput
+//! regressions, so configuration changes can be gated on benchmark numbers.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use prettytable::{row, Table};
+use serde_json::Value;
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// Results of the reference run (--output csv, json or jsonl)
+    baseline: PathBuf,
+
+    /// Results of the run to check against the baseline
+    new: PathBuf,
+
+    /// Fail when rows/sec of any result drops by more than this percentage
+    #[arg(long, default_value = "5.0")]
+    threshold: f64,
+}
+
+/// What identifies the same benchmark in two runs
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct ResultKey {
+    connection: String,
+    // Empty for results written before table variants
+    table: String,
+    method: String,
+    batch_size: u64,
+    transaction: bool,
+    // False for results written before TLS was recorded
+    tls: bool,
+    threads: u64,
+    // Session settings by name
+    settings: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for ResultKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} on {}{}, batch size {}, {} threads{}{}",
+            self.method,
+            self.connection,
+            if self.table.is_empty() { String::new() } else { format!(" ({})", self.table) },
+            self.batch_size,
+            self.threads,
+            if self.transaction { ", in a transaction" } else { "" },
+            if self.tls { ", over TLS" } else { "" }
+        )?;
+        for (name, value) in &self.settings {
+            write!(f, ", {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+type Results = HashMap<ResultKey, f64>;
+
+pub fn run(args: &CompareArgs) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let baseline = load(&args.baseline)?;
+    let new = load(&args.new)?;
+
+    let mut keys: Vec<&ResultKey> = baseline.keys().filter(|key| new.contains_key(key)).collect();
+    keys.sort();
+    if keys.is_empty() {
+        return Err(format!(
+            "No results in {} match one in {}",
+            args.new.display(),
+            args.baseline.display()
+        ).into());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        b->"Connection",
+        b->"Table",
+        b->"Method",
+        b->"Batch Size",
+        b->"Transaction",
+        b->"TLS",
+        b->"Threads",
+        b->"Settings",
+        b->"Baseline Rows/sec",
+        b->"New Rows/sec",
+        b->"Change",
+        b->""
+    ]);
+    let mut regressions = 0;
+    for key in keys {
+        let (before, after) = (baseline[key], new[key]);
+        let change = if before > 0.0 { (after - before) / before * 100.0 } else { 0.0 };
+        let regressed = change < -args.threshold;
+        if regressed {
+            regressions += 1;
+        }
+        table.add_row(row![
+            key.connection,
+            key.table,
+            key.method,
+            format!("{}", key.batch_size),
+            if key.transaction { "Yes" } else { "No" },
+            if key.tls { "Yes" } else { "No" },
+            format!("{}", key.threads),
+            key.settings.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join(" "),
+            format!("{:.0}", before),
+            format!("{:.0}", after),
+            format!("{:+.1}%", change),
+            if regressed { "REGRESSION" } else { "" }
+        ]);
+    }
+    table.printstd();
+
+    for key in baseline.keys().filter(|key| !new.contains_key(key)) {
+        eprintln!("Only in baseline: {}", key);
+    }
+    for key in new.keys().filter(|key| !baseline.contains_key(key)) {
+        eprintln!("Only in new run: {}", key);
+    }
+
+    if regressions > 0 {
+        return Err(format!("{} results regressed by more than {}%", regressions, args.threshold).into());
+    }
+    Ok(())
+}
+
+// Reads results written with --output csv, json or jsonl, detected from the content.
+// Fails when two results have the same key, as only one of them could be compared.
+fn load(path: &Path) -> Result<Results, Box<dyn Error + Send + Sync>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+    let results = if content.trim_start().starts_with('{') {
+        load_json(&content)
+    } else {
+        load_csv(&content)
+    };
+    let results = results.map_err(|e| format!("Can't read results from {}: {}", path.display(), e))?;
+
+    let mut by_key = Results::new();
+    for (key, rows_per_sec) in results {
+        if by_key.contains_key(&key) {
+            return Err(format!("{} has more than one result for {}", path.display(), key).into());
+        }
+        by_key.insert(key, rows_per_sec);
+    }
+    Ok(by_key)
+}
+
+fn load_json(content: &str) -> Result<Vec<(ResultKey, f64)>, Box<dyn Error + Send + Sync>> {
+    let objects: Vec<Value> = match serde_json::from_str::<Value>(content) {
+        // --output json; a jsonl file with one result is a single document too
+        Ok(mut document) if document.get("results").is_some() => match document["results"].take() {
+            Value::Array(results) => results,
+            _ => return Err("no results array".into()),
+        },
+        // --output jsonl
+        _ => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+    objects
+        .iter()
+        .map(|object| {
+            let text = |field: &str| object[field].as_str().map(str::to_string).ok_or_else(|| format!("missing {}", field));
+            let number = |field: &str| object[field].as_u64().ok_or_else(|| format!("missing {}", field));
+            let mut key = ResultKey {
+                connection: text("connection")?,
+                table: object["table"].as_str().unwrap_or_default().to_string(),
+                method: text("method")?,
+                batch_size: number("batch_size")?,
+                transaction: object["transaction"].as_bool().ok_or("missing transaction")?,
+                tls: object["tls"].as_bool().unwrap_or_default(),
+                threads: number("threads")?,
+                settings: object["settings"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, value)| (name.clone(), value.as_str().unwrap_or_default().to_string()))
+                    .collect(),
+            };
+            key.settings.sort();
+            let rows_per_sec = object["rows_per_sec"].as_f64().ok_or("missing rows_per_sec")?;
+            Ok((key, rows_per_sec))
+        })
+        .collect()
+}
+
+fn load_csv(content: &str) -> Result<Vec<(ResultKey, f64)>, Box<dyn Error + Send + Sync>> {
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name).ok_or_else(|| format!("no {} column", name));
+    let (connection, method, batch_size, transaction, threads, rows_per_sec) = (
+        column("Connection")?,
+        column("Method")?,
+        column("Batch Size")?,
+        column("Transaction")?,
+        column("Threads")?,
+        column("Rows/sec")?,
+    );
+    let table = headers.iter().position(|h| h == "Table");
+    let tls = headers.iter().position(|h| h == "TLS");
+    let settings: Vec<(usize, &str)> = headers
+        .iter()
+        .enumerate()
+        .filter_map(|(i, h)| Some((i, h.strip_prefix("set:")?)))
+        .collect();
+    let mut results = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let mut key = ResultKey {
+            connection: record[connection].to_string(),
+            table: table.map_or(String::new(), |table| record[table].to_string()),
+            method: record[method].to_string(),
+            batch_size: record[batch_size].parse()?,
+            transaction: &record[transaction] == "Yes",
+            tls: tls.is_some_and(|tls| &record[tls] == "Yes"),
+            threads: record[threads].parse()?,
+            settings: settings.iter().map(|(i, name)| (name.to_string(), record[*i].to_string())).collect(),
+        };
+        key.settings.sort();
+        results.push((key, record[rows_per_sec].parse()?));
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESULT: &str = r#"{"connection":"local","table":"power_generation","method":"Copy","batch_size":1000,"transaction":false,"tls":false,"threads":1,"settings":{},"rows_per_sec":1234.5,"run":{"rows":10}}"#;
+
+    fn key(method: &str) -> ResultKey {
+        ResultKey {
+            connection: "local".to_string(),
+            table: "power_generation".to_string(),
+            method: method.to_string(),
+            batch_size: 1000,
+            transaction: false,
+            tls: false,
+            threads: 1,
+            settings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn load_json_single_line_jsonl() {
+        assert_eq!(load_json(&format!("{}\n", RESULT)).unwrap(), [(key("Copy"), 1234.5)]);
+    }
+
+    #[test]
+    fn load_json_document_and_lines() {
+        let second = RESULT.replace(r#""Copy""#, r#""Binary Copy""#).replace("1234.5", "99.0");
+        let expected = [(key("Copy"), 1234.5), (key("Binary Copy"), 99.0)];
+        let document = format!(r#"{{"run":{{}},"results":[{},{}]}}"#, RESULT, second);
+        assert_eq!(load_json(&document).unwrap(), expected);
+        assert_eq!(load_json(&format!("{}\n\n{}\n", RESULT, second)).unwrap(), expected);
+        assert!(load_json(r#"{"results":{}}"#).is_err());
+    }
+}
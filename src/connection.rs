@@ -0,0 +1,565 @@
+//! Benchmark targets, resolved the way libpq resolves a connection: from a URL or a
+//! key/value connection string, a `pg_service.conf` service, the `PG*` environment
+//! variables and `.pgpass`, in that order of precedence.
+//!
+//! A target is written as `[name=]conninfo`:
+//!
+//! - `prod=postgresql://user@db.example.com/metrics` names the connection `prod`
+//! - `host=db.example.com dbname=metrics` is an unnamed key/value connection string
+//! - `prod=service=metrics` or an empty string with `PGSERVICE` set use a service
+//! - `prod://user@db.example.com/metrics`, the older form with the name as URL scheme,
+//!   still works
+//!
+//! The result is a normalized key/value connection string for tokio-postgres, with the
+//! TLS parameters moved to [`TlsOptions`].
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::tls::{SslMode, TlsOptions};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+type Params = BTreeMap<String, String>;
+
+// Every libpq connection keyword (PQconndefaults() of libpq 18), to tell `host=x` from a
+// connection named `host`
+const KEYWORDS: &[&str] = &[
+    "application_name", "channel_binding", "client_encoding", "connect_timeout", "dbname",
+    "fallback_application_name", "gssdelegation", "gssencmode", "gsslib", "host", "hostaddr", "keepalives",
+    "keepalives_count", "keepalives_idle", "keepalives_interval", "krbsrvname", "load_balance_hosts",
+    "max_protocol_version", "min_protocol_version", "oauth_client_id", "oauth_client_secret", "oauth_issuer",
+    "oauth_scope", "options", "passfile", "password", "port", "replication", "require_auth", "requirepeer",
+    "scram_client_key", "scram_server_key", "service", "ssl_max_protocol_version", "ssl_min_protocol_version",
+    "sslcert", "sslcertmode", "sslcompression", "sslcrl", "sslcrldir", "sslkey", "sslkeylogfile", "sslmode",
+    "sslnegotiation", "sslpassword", "sslrootcert", "sslsni", "target_session_attrs", "tcp_user_timeout", "user",
+];
+
+// Looks up an environment variable
+type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+// The keywords tokio-postgres accepts, besides sslmode; render() drops the others
+const SUPPORTED: &[&str] = &[
+    "application_name", "channel_binding", "connect_timeout", "dbname", "host", "hostaddr", "keepalives",
+    "keepalives_idle", "keepalives_interval", "keepalives_retries", "load_balance_hosts", "options",
+    "password", "port", "target_session_attrs", "tcp_user_timeout", "user",
+];
+
+// Environment variables libpq falls back to for unset parameters
+const ENVIRONMENT: &[(&str, &str)] = &[
+    ("PGHOST", "host"),
+    ("PGHOSTADDR", "hostaddr"),
+    ("PGPORT", "port"),
+    ("PGDATABASE", "dbname"),
+    ("PGUSER", "user"),
+    ("PGPASSWORD", "password"),
+    ("PGPASSFILE", "passfile"),
+    ("PGOPTIONS", "options"),
+    ("PGAPPNAME", "application_name"),
+    ("PGCONNECT_TIMEOUT", "connect_timeout"),
+    ("PGTARGETSESSIONATTRS", "target_session_attrs"),
+    ("PGCHANNELBINDING", "channel_binding"),
+    ("PGLOADBALANCEHOSTS", "load_balance_hosts"),
+    ("PGSSLMODE", "sslmode"),
+    ("PGSSLROOTCERT", "sslrootcert"),
+    ("PGSSLCERT", "sslcert"),
+    ("PGSSLKEY", "sslkey"),
+];
+
+const DEFAULT_PORT: &str = "5432";
+
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub name: String,
+    /// Key/value connection string for tokio-postgres, including the sslmode to negotiate
+    pub connection_string: String,
+    pub tls: TlsOptions,
+    // Resolved parameters without the TLS ones
+    params: Params,
+}
+
+impl ConnectionInfo {
+    /// Resolves one `[name=]conninfo` target. `defaults` are parameters from command-line
+    /// flags, used where neither the connection string nor its service set them.
+    pub fn parse(s: &str, unnamed_ordinal: usize, defaults: &[(&str, String)]) -> Result<Self, BoxError> {
+        Self::resolve(s, unnamed_ordinal, defaults, &|variable| std::env::var(variable).ok())
+    }
+
+    // parse() with the environment given by `env`
+    fn resolve(s: &str, unnamed_ordinal: usize, defaults: &[(&str, String)], env: Env) -> Result<Self, BoxError> {
+        let (name, conninfo) = split_name(s.trim());
+        let (name, mut params) = match conninfo.split_once("://") {
+            Some(("postgresql" | "postgres", _)) => (name, parse_url(conninfo)?),
+            // Older syntax: the scheme names the connection
+            Some((scheme, rest)) => (name.or(Some(scheme.to_string())), parse_url(&format!("postgresql://{}", rest))?),
+            None if conninfo.contains('=') => (name, parse_key_value(conninfo)?),
+            None if conninfo.is_empty() => (name, Params::new()),
+            None => (name, parse_url(&format!("postgresql://{}", conninfo))?),
+        };
+
+        let service = params.remove("service").or_else(|| env("PGSERVICE"));
+        if let Some(service) = &service {
+            for (key, value) in read_service(service, env)? {
+                params.entry(key).or_insert(value);
+            }
+        }
+        for (key, value) in defaults {
+            params.entry(key.to_string()).or_insert_with(|| value.clone());
+        }
+        for (variable, key) in ENVIRONMENT {
+            if let Some(value) = env(variable) {
+                params.entry(key.to_string()).or_insert(value);
+            }
+        }
+        apply_defaults(&mut params, env);
+        let passfile = params.remove("passfile");
+        if !params.contains_key("password") {
+            if let Some(password) = pgpass_password(&params, passfile.as_deref(), env)? {
+                params.insert("password".to_string(), password);
+            }
+        }
+        let tls = TlsOptions::take_from(&mut params)?;
+
+        let name = name
+            .or(service)
+            .unwrap_or_else(|| format!("connection {}", unnamed_ordinal + 1));
+        for key in keep_supported(&mut params) {
+            eprintln!("Ignoring connection parameter {} of {}, which tokio-postgres doesn't support", key, name);
+        }
+        let connection_string = render(&params, tls.mode);
+        Ok(ConnectionInfo { name, connection_string, tls, params })
+    }
+
+    /// The same connection with a different sslmode
+    pub fn with_sslmode(&self, mode: SslMode) -> Self {
+        ConnectionInfo {
+            name: self.name.clone(),
+            connection_string: render(&self.params, mode),
+            tls: TlsOptions { mode, ..self.tls.clone() },
+            params: self.params.clone(),
+        }
+    }
+}
+
+// Splits off `name=` unless the part before `=` is a connection keyword
+fn split_name(s: &str) -> (Option<String>, &str) {
+    match s.split_once('=') {
+        Some((name, rest))
+            if !name.is_empty()
+                && !KEYWORDS.contains(&name.trim())
+                && name.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c)) =>
+        {
+            (Some(name.to_string()), rest.trim())
+        }
+        _ => (None, s),
+    }
+}
+
+// postgresql://[user[:password]@][host[:port][,...]][/dbname][?key=value&...]
+fn parse_url(url: &str) -> Result<Params, BoxError> {
+    let invalid = |what: &str| format!("Invalid connection URL, {}", what);
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (authority, dbname) = rest.split_once('/').unwrap_or((rest, ""));
+    let (userinfo, hosts) = authority.rsplit_once('@').unwrap_or(("", authority));
+
+    let mut params = Params::new();
+    if !userinfo.is_empty() {
+        let (user, password) = userinfo.split_once(':').map_or((userinfo, None), |(u, p)| (u, Some(p)));
+        if !user.is_empty() {
+            params.insert("user".to_string(), percent_decode(user)?);
+        }
+        if let Some(password) = password {
+            params.insert("password".to_string(), percent_decode(password)?);
+        }
+    }
+    if !hosts.is_empty() {
+        let mut host_list = Vec::new();
+        let mut port_list = Vec::new();
+        for hostspec in hosts.split(',') {
+            // [v6 address]:port
+            let (host, port) = match hostspec.strip_prefix('[') {
+                Some(bracketed) => {
+                    let (host, after) = bracketed.split_once(']').ok_or_else(|| invalid("unclosed ["))?;
+                    (host, after.strip_prefix(':'))
+                }
+                None => hostspec.split_once(':').map_or((hostspec, None), |(h, p)| (h, Some(p))),
+            };
+            host_list.push(percent_decode(host)?);
+            port_list.push(port.filter(|port| !port.is_empty()));
+        }
+        if host_list.iter().any(|host| !host.is_empty()) {
+            params.insert("host".to_string(), host_list.join(","));
+        }
+        if port_list.iter().any(Option::is_some) {
+            let ports: Vec<_> = port_list.iter().map(|port| port.unwrap_or(DEFAULT_PORT)).collect();
+            params.insert("port".to_string(), ports.join(","));
+        }
+    }
+    if !dbname.is_empty() {
+        params.insert("dbname".to_string(), percent_decode(dbname)?);
+    }
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (key, value) = param.split_once('=').ok_or_else(|| invalid(&format!("parameter {} has no value", param)))?;
+        params.insert(percent_decode(key)?, percent_decode(value)?);
+    }
+    Ok(params)
+}
+
+fn percent_decode(s: &str) -> Result<String, BoxError> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(|| format!("Invalid percent-encoding in '{}'", s))?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| format!("Invalid percent-encoding in '{}'", s))?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(decoded)?)
+}
+
+// key=value pairs separated by whitespace; values may be single-quoted, with \ escapes
+fn parse_key_value(s: &str) -> Result<Params, BoxError> {
+    let mut params = Params::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(params);
+        }
+        let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=' && !c.is_whitespace())).collect();
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next() != Some('=') {
+            return Err(format!("Invalid connection string, missing \"=\" after \"{}\"", key).into());
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quoted = chars.next_if_eq(&'\'').is_some();
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some('\\') => value.extend(chars.next()),
+                Some('\'') if quoted => break,
+                Some(c) if c.is_whitespace() && !quoted => break,
+                Some(c) => value.push(c),
+                None if quoted => return Err(format!("Invalid connection string, unterminated quote after \"{}\"", key).into()),
+                None => break,
+            }
+        }
+        params.insert(key, value);
+    }
+}
+
+// Maps libpq keywords to their tokio-postgres equivalents and removes the ones it
+// rejects, returning the names of those
+fn keep_supported(params: &mut Params) -> Vec<String> {
+    if let Some(count) = params.remove("keepalives_count") {
+        params.entry("keepalives_retries".to_string()).or_insert(count);
+    }
+    if let Some(fallback) = params.remove("fallback_application_name") {
+        params.entry("application_name".to_string()).or_insert(fallback);
+    }
+    let unsupported: Vec<String> = params.keys().filter(|key| !SUPPORTED.contains(&key.as_str())).cloned().collect();
+    for key in &unsupported {
+        params.remove(key);
+    }
+    unsupported
+}
+
+fn render(params: &Params, sslmode: SslMode) -> String {
+    let quote = |value: &str| format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"));
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, quote(value)))
+        .chain(std::iter::once(format!("sslmode={}", sslmode.negotiated())))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// What libpq assumes when nothing sets these
+fn apply_defaults(params: &mut Params, env: Env) {
+    if !params.contains_key("user") {
+        if let Some(user) = env("USER").or_else(|| env("LOGNAME")) {
+            params.insert("user".to_string(), user);
+        }
+    }
+    if !params.contains_key("host") && !params.contains_key("hostaddr") {
+        let port = params.get("port").map_or(DEFAULT_PORT, String::as_str);
+        let socket_dir = ["/var/run/postgresql", "/tmp"]
+            .into_iter()
+            .find(|dir| Path::new(dir).join(format!(".s.PGSQL.{}", port)).exists());
+        params.insert("host".to_string(), socket_dir.unwrap_or("localhost").to_string());
+    }
+}
+
+// The [service] section of the first service file that has it
+fn read_service(service: &str, env: Env) -> Result<Params, BoxError> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    match env("PGSERVICEFILE") {
+        Some(file) => files.push(file.into()),
+        None => files.extend(home_file(".pg_service.conf", env)),
+    }
+    match env("PGSYSCONFDIR") {
+        Some(dir) => files.push(Path::new(&dir).join("pg_service.conf")),
+        None => files.extend(["/etc/postgresql-common/pg_service.conf", "/etc/pg_service.conf"].map(PathBuf::from)),
+    }
+    for file in files.iter().filter(|file| file.exists()) {
+        let content = std::fs::read_to_string(file).map_err(|e| format!("Can't read {}: {}", file.display(), e))?;
+        let mut in_section = false;
+        let mut params = Params::new();
+        for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                if in_section {
+                    break;
+                }
+                in_section = section == service;
+            } else if in_section {
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid line in {}: {}", file.display(), line))?;
+                params.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+        if in_section {
+            return Ok(params);
+        }
+    }
+    Err(format!("Service \"{}\" not found in {:?}", service, files).into())
+}
+
+// Password from the password file (passfile, PGPASSFILE or ~/.pgpass) for the first
+// host with a matching line
+fn pgpass_password(params: &Params, passfile: Option<&str>, env: Env) -> Result<Option<String>, BoxError> {
+    let Some(path) = passfile.map(PathBuf::from).or_else(|| home_file(".pgpass", env)) else {
+        return Ok(None);
+    };
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::metadata(&path)?.permissions().mode() & 0o077 != 0 {
+            eprintln!(
+                "Password file {} has group or world access; permissions should be u=rw (0600) or less",
+                path.display()
+            );
+            return Ok(None);
+        }
+    }
+
+    let hosts = params.get("host").or(params.get("hostaddr")).map_or("", String::as_str);
+    let ports: Vec<&str> = params.get("port").map_or(vec![DEFAULT_PORT], |ports| ports.split(',').collect());
+    let user = params.get("user").map_or("", String::as_str);
+    let dbname = params.get("dbname").map_or(user, String::as_str);
+    for (i, host) in hosts.split(',').enumerate() {
+        // Sockets match "localhost", like in libpq
+        let host = if host.starts_with('/') { "localhost" } else { host };
+        let port = ports.get(i).or(ports.first()).copied().unwrap_or(DEFAULT_PORT);
+        let wanted = [host, port, dbname, user];
+        for line in content.lines().filter(|line| !line.starts_with('#')) {
+            let fields = pgpass_fields(line);
+            if fields.len() == 5 && fields[..4].iter().zip(wanted).all(|(field, value)| field == "*" || field == value) {
+                return Ok(Some(fields[4].clone()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// Colon-separated fields, where \: and \\ stand for : and \
+fn pgpass_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+fn home_file(name: &str, env: Env) -> Option<PathBuf> {
+    Some(Path::new(&env("HOME")?).join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_url_hosts_and_decoding() {
+        let parsed = parse_url(
+            "postgresql://bench:p%40ss%3Aword@[::1]:5433,db2,[fe80::1%25eth0]/my%20db?application_name=a%26b&connect_timeout=5",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            params(&[
+                ("user", "bench"),
+                ("password", "p@ss:word"),
+                ("host", "::1,db2,fe80::1%eth0"),
+                ("port", "5433,5432,5432"),
+                ("dbname", "my db"),
+                ("application_name", "a&b"),
+                ("connect_timeout", "5"),
+            ])
+        );
+        assert_eq!(parse_url("postgres://db:/metrics").unwrap(), params(&[("host", "db"), ("dbname", "metrics")]));
+        assert_eq!(parse_url("postgresql://").unwrap(), Params::new());
+        assert_eq!(parse_url("postgresql:///metrics?host=/tmp").unwrap(), params(&[("dbname", "metrics"), ("host", "/tmp")]));
+    }
+
+    #[test]
+    fn parse_url_rejects_malformed_urls() {
+        assert!(parse_url("postgresql://[::1/db").is_err());
+        assert!(parse_url("postgresql://db/metrics?sslmode").is_err());
+        assert!(parse_url("postgresql://db/bad%zz").is_err());
+        assert!(parse_url("postgresql://db/cut%2").is_err());
+    }
+
+    #[test]
+    fn parse_key_value_quotes_and_escapes() {
+        let parsed = parse_key_value(r"host=db  port = 5433 password='it\'s a \\secret' options='-c x=1' dbname=a\ b user=''").unwrap();
+        assert_eq!(
+            parsed,
+            params(&[
+                ("host", "db"),
+                ("port", "5433"),
+                ("password", r"it's a \secret"),
+                ("options", "-c x=1"),
+                ("dbname", "a b"),
+                ("user", ""),
+            ])
+        );
+        assert!(parse_key_value("host db").is_err());
+        assert!(parse_key_value("password='open").is_err());
+    }
+
+    #[test]
+    fn split_name_keeps_keywords() {
+        assert_eq!(split_name("prod=postgresql://db/x"), (Some("prod".to_string()), "postgresql://db/x"));
+        assert_eq!(split_name("prod=service=metrics"), (Some("prod".to_string()), "service=metrics"));
+        assert_eq!(split_name("host=db dbname=x"), (None, "host=db dbname=x"));
+        assert_eq!(split_name("prod://user@db/x"), (None, "prod://user@db/x"));
+        assert_eq!(split_name("postgresql://db/x?sslmode=require"), (None, "postgresql://db/x?sslmode=require"));
+        assert_eq!(split_name("=x"), (None, "=x"));
+        for keyword in ["sslsni", "sslnegotiation", "sslcrl", "gssencmode", "krbsrvname", "ssl_min_protocol_version"] {
+            let conninfo = format!("{}=1 host=db sslmode=disable", keyword);
+            assert_eq!(split_name(&conninfo), (None, conninfo.as_str()));
+        }
+    }
+
+    #[test]
+    fn pgpass_fields_unescape() {
+        assert_eq!(pgpass_fields(r"db:5432:metrics:bench:p\:w\\d"), ["db", "5432", "metrics", "bench", r"p:w\d"]);
+        assert_eq!(pgpass_fields("*:*:*:bench:a:b"), ["*", "*", "*", "bench", "a:b"]);
+        assert_eq!(pgpass_fields("db:5432"), ["db", "5432"]);
+    }
+
+    #[test]
+    fn keep_supported_maps_libpq_keywords() {
+        let mut resolved = params(&[
+            ("host", "db"),
+            ("keepalives_count", "3"),
+            ("fallback_application_name", "fallback"),
+            ("gssencmode", "disable"),
+            ("client_encoding", "UTF8"),
+        ]);
+        assert_eq!(keep_supported(&mut resolved), ["client_encoding", "gssencmode"]);
+        assert_eq!(
+            resolved,
+            params(&[("host", "db"), ("keepalives_retries", "3"), ("application_name", "fallback")])
+        );
+        let mut named = params(&[("application_name", "bench"), ("fallback_application_name", "fallback")]);
+        assert!(keep_supported(&mut named).is_empty());
+        assert_eq!(named, params(&[("application_name", "bench")]));
+    }
+
+    #[test]
+    fn services_and_precedence() {
+        let dir = std::env::temp_dir().join(format!("pgingester-connection-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let service_file = dir.join("pg_service.conf");
+        std::fs::write(
+            &service_file,
+            "[other]\nhost=wrong\n\n[bench]\n# comment\nhost = svc-host\nport=6000\ndbname=svcdb\nuser=svcuser\n[after]\nport=1\n",
+        )
+        .unwrap();
+        let passfile = dir.join("pgpass");
+        std::fs::write(&passfile, "other:*:*:*:wrong\nsvc-host:6000:svcdb:urluser:secret\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&passfile, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        let mut environment: BTreeMap<&str, String> = [
+            ("PGSERVICEFILE", service_file.display().to_string()),
+            ("PGSYSCONFDIR", dir.join("missing").display().to_string()),
+            ("PGPASSFILE", passfile.display().to_string()),
+            ("PGDATABASE", "envdb".to_string()),
+            ("PGUSER", "envuser".to_string()),
+            ("PGAPPNAME", "envapp".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let env = |environment: &BTreeMap<&str, String>, variable: &str| environment.get(variable).cloned();
+
+        let without_service = environment.clone();
+        let no_service = |variable: &str| env(&without_service, variable);
+        assert_eq!(
+            read_service("bench", &no_service).unwrap(),
+            params(&[("host", "svc-host"), ("port", "6000"), ("dbname", "svcdb"), ("user", "svcuser")])
+        );
+        assert!(read_service("missing", &no_service).is_err());
+
+        // Connection string, then service, then flags, then environment, then .pgpass
+        let defaults = [("application_name", "flagapp".to_string()), ("dbname", "flagdb".to_string())];
+        let info = ConnectionInfo::resolve("postgresql://urluser@/?service=bench", 0, &defaults, &no_service).unwrap();
+        assert_eq!(info.name, "bench");
+        assert_eq!(
+            info.params,
+            params(&[
+                ("user", "urluser"),
+                ("host", "svc-host"),
+                ("port", "6000"),
+                ("dbname", "svcdb"),
+                ("application_name", "flagapp"),
+                ("password", "secret"),
+            ])
+        );
+
+        let info = ConnectionInfo::resolve("host=db password=given", 1, &defaults, &no_service).unwrap();
+        assert_eq!(info.name, "connection 2");
+        assert_eq!(
+            info.params,
+            params(&[
+                ("host", "db"),
+                ("password", "given"),
+                ("dbname", "flagdb"),
+                ("application_name", "flagapp"),
+                ("user", "envuser"),
+            ])
+        );
+
+        environment.insert("PGSERVICE", "bench".to_string());
+        let info = ConnectionInfo::resolve("prod=", 0, &[], &|variable| env(&environment, variable)).unwrap();
+        assert_eq!(info.name, "prod");
+        assert_eq!(info.params["user"], "svcuser");
+        assert_eq!(info.params["application_name"], "envapp");
+        assert!(!info.params.contains_key("password"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
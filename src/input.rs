@@ -0,0 +1,260 @@
+//! CSV input. Columns are matched to the target table by header name, optionally
+//! renamed with `--column-map csv_col=table_col`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+use clap::ValueEnum;
+use csv::{ReaderBuilder, StringRecord};
+use postgres::types::Type;
+
+use crate::row::{Row, Value};
+use crate::schema::TableSchema;
+
+/// How CSV fields are assigned to table columns
+#[derive(Debug, Clone, Default)]
+pub struct ColumnMapping {
+    /// Explicit `csv_col=table_col` renames
+    pub renames: Vec<(String, String)>,
+    /// Ignore the header and take fields in table column order
+    pub positional: bool,
+}
+
+/// Parses a `key=value` command-line argument
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .ok_or_else(|| format!("expected key=value, got '{}'", s))
+}
+
+/// Returns, for each table column, the index of the CSV field that feeds it
+pub fn column_indices(
+    headers: &StringRecord,
+    schema: &TableSchema,
+    mapping: &ColumnMapping,
+) -> Result<Vec<usize>, Box<dyn Error + Send + Sync>> {
+    if mapping.positional {
+        if headers.len() < schema.columns.len() {
+            return Err(format!(
+                "Input has {} columns, table {} has {}",
+                headers.len(), schema.table, schema.columns.len()
+            ).into());
+        }
+        return Ok((0..schema.columns.len()).collect());
+    }
+
+    let header_index: HashMap<&str, usize> = headers.iter().enumerate().map(|(i, h)| (h.trim(), i)).collect();
+    let mut targets: HashMap<String, usize> = HashMap::new();
+    for (csv_col, table_col) in &mapping.renames {
+        let index = *header_index
+            .get(csv_col.as_str())
+            .ok_or_else(|| format!("--column-map refers to CSV column {} which is not in the header", csv_col))?;
+        if !schema.columns.iter().any(|c| &c.name == table_col) {
+            return Err(format!("--column-map refers to column {} which is not in table {}", table_col, schema.table).into());
+        }
+        targets.insert(table_col.clone(), index);
+    }
+    let renamed: Vec<&str> = mapping.renames.iter().map(|(csv_col, _)| csv_col.as_str()).collect();
+    for (name, index) in &header_index {
+        if !renamed.contains(name) {
+            targets.entry(name.to_string()).or_insert(*index);
+        }
+    }
+
+    let mut indices = Vec::with_capacity(schema.columns.len());
+    let mut missing = Vec::new();
+    for column in &schema.columns {
+        match targets.get(&column.name) {
+            Some(index) => indices.push(*index),
+            None => missing.push(column.name.as_str()),
+        }
+    }
+    let extra: Vec<&str> = headers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !indices.contains(i))
+        .map(|(_, h)| h.trim())
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(format!(
+            "CSV header doesn't cover every column of table {} (missing: {}; extra CSV columns: {}). \
+             Map them with --column-map csv_col=table_col, or pass --positional to ignore the header",
+            schema.table,
+            missing.join(", "),
+            if extra.is_empty() { "(none)".to_string() } else { extra.join(", ") }
+        ).into());
+    }
+    if !extra.is_empty() {
+        eprintln!("Ignoring CSV columns not in table {}: {}", schema.table, extra.join(", "));
+    }
+    Ok(indices)
+}
+
+/// What to do with input rows that fail to parse
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Drop the row and carry on
+    Skip,
+    /// Drop the row and record it in the reject file
+    Quarantine,
+    /// Abort the run
+    Fail,
+}
+
+/// Counts rejected input rows and, in quarantine mode, writes them to the reject file
+pub struct Rejects {
+    mode: OnError,
+    writer: Option<csv::Writer<File>>,
+    pub count: usize,
+}
+
+impl Rejects {
+    pub fn new(mode: OnError, path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let writer = if mode == OnError::Quarantine {
+            let mut writer = csv::Writer::from_path(path)?;
+            writer.write_record(["line", "error", "raw_line"])?;
+            Some(writer)
+        } else {
+            None
+        };
+        Ok(Rejects { mode, writer, count: 0 })
+    }
+
+    fn reject(&mut self, line: u64, raw_line: &str, error: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.mode == OnError::Fail {
+            return Err(format!("Line {}: {}", line, error).into());
+        }
+        self.count += 1;
+        if let Some(writer) = &mut self.writer {
+            writer.write_record([line.to_string().as_str(), error, raw_line])?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The reject file of `table`: `path` itself for a single table, otherwise `path` with
+/// the table name before the extension, such as `rejects.power_generation.csv`
+pub fn reject_file_for(path: &str, table: &str, several_tables: bool) -> String {
+    if !several_tables {
+        return path.to_string();
+    }
+    let path = std::path::Path::new(path);
+    let file_name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => {
+            format!("{}.{}.{}", stem.to_string_lossy(), table, extension.to_string_lossy())
+        }
+        _ => format!("{}.{}", path.to_string_lossy(), table),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+// Re-serializes a record as a CSV line for the reject file
+fn raw_line(record: &StringRecord) -> String {
+    let mut writer = csv::WriterBuilder::new().terminator(csv::Terminator::Any(b'\n')).from_writer(Vec::new());
+    let line = writer
+        .write_record(record)
+        .ok()
+        .and_then(|_| writer.into_inner().ok())
+        .unwrap_or_default();
+    String::from_utf8_lossy(&line).trim_end().to_string()
+}
+
+fn parse_record(record: &StringRecord, indices: &[usize], types: &[Type]) -> Result<Row, Box<dyn Error + Send + Sync>> {
+    let needed = indices.iter().max().map_or(0, |&i| i + 1);
+    if record.len() < needed {
+        return Err(format!("expected at least {} fields, found {}", needed, record.len()).into());
+    }
+    indices
+        .iter()
+        .zip(types)
+        .map(|(&i, ty)| Value::parse(&record[i], ty))
+        .collect()
+}
+
+/// Parsed rows of a CSV file, read lazily. Rows that fail to parse go to `rejects`.
+pub struct CsvRows<'a> {
+    reader: csv::Reader<BufReader<File>>,
+    indices: Vec<usize>,
+    types: Vec<Type>,
+    rejects: &'a mut Rejects,
+    done: bool,
+}
+
+impl<'a> CsvRows<'a> {
+    pub fn open(
+        path: &str,
+        schema: &TableSchema,
+        mapping: &ColumnMapping,
+        rejects: &'a mut Rejects,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        // Rows with the wrong number of fields are rejected below rather than by the reader
+        let mut reader = ReaderBuilder::new().flexible(true).from_reader(reader);
+        let indices = column_indices(reader.headers()?, schema, mapping)?;
+        Ok(CsvRows {
+            reader,
+            indices,
+            types: schema.types(),
+            rejects,
+            done: false,
+        })
+    }
+
+    fn next_row(&mut self) -> Result<Option<Row>, Box<dyn Error + Send + Sync>> {
+        let mut record = StringRecord::new();
+        loop {
+            match self.reader.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.rejects.finish()?;
+                    return Ok(None);
+                }
+                Err(e) => {
+                    let line = e.position().map_or(0, |p| p.line());
+                    self.rejects.reject(line, "", &e.to_string())?;
+                    continue;
+                }
+            }
+            match parse_record(&record, &self.indices, &self.types) {
+                Ok(row) => return Ok(Some(row)),
+                Err(e) => {
+                    let line = record.position().map_or(0, |p| p.line());
+                    self.rejects.reject(line, &raw_line(&record), &e.to_string())?;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for CsvRows<'_> {
+    type Item = Result<Row, Box<dyn Error + Send + Sync>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let row = self.next_row().transpose();
+        self.done = !matches!(row, Some(Ok(_)));
+        row
+    }
+}
+
+pub fn read_csv(
+    path: &str,
+    schema: &TableSchema,
+    mapping: &ColumnMapping,
+    rejects: &mut Rejects,
+) -> Result<Vec<Row>, Box<dyn Error + Send + Sync>> {
+    CsvRows::open(path, schema, mapping, rejects)?.collect()
+}
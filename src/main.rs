@@ -1,81 +1,202 @@
-use postgres::types::Type;
+use postgres::types::{ToSql, Type};
 use std::io::Write;
-use chrono::{DateTime, Utc};
-use csv::Reader;
-use postgres::{Client, NoTls};
+use postgres::Client;
 use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
 use std::env;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use postgres::binary_copy::BinaryCopyInWriter;
 use prettytable::{Table, row};
-use postgres_openssl::MakeTlsConnector;
-use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
-use rayon::prelude::*;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-#[derive(Debug, Clone)]
-struct ConnectionInfo {
-    name: String,
-    connection_string: String,
-}
-
-impl ConnectionInfo {
-    fn from_str(s: &str, unnamed_ordinal: usize) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let connection_string = if s.starts_with("postgresql://") || s.starts_with("postgres://") {
-            s.to_string()
-        } else if let Some(rest) = s.strip_prefix("://") {
-            format!("postgresql://{}", rest)
-        } else if let Some((prefix, rest)) = s.split_once("://") {
-            format!("postgresql://{}", rest)
-        } else {
-            format!("postgresql://{}", s)
-        };
-
-        let name = if let Some((prefix, _)) = s.split_once("://") {
-            if prefix == "postgresql" || prefix == "postgres" {
-                format!("connection {}", unnamed_ordinal + 1)
-            } else {
-                prefix.to_string()
-            }
-        } else {
-            format!("postgres-{}", unnamed_ordinal + 1)
-        };
+mod async_engine;
+mod batches;
+mod compare;
+mod connection;
+mod input;
+mod report;
+mod row;
+mod scenario;
+mod schema;
+mod server_stats;
+mod stats;
+mod synthetic;
+mod tls;
 
-        Ok(ConnectionInfo {
-            name,
-            connection_string,
-        })
-    }
-}
+use batches::{Batches, WorkerInput};
+use connection::ConnectionInfo;
+use input::{ColumnMapping, CsvRows, OnError, Rejects};
+use row::{ColumnArray, Row};
+use scenario::{Matrix, TableVariant};
+use schema::{ConflictAction, TableSchema};
+use server_stats::{FrozenPages, ServerMetrics};
+use stats::{LatencyHistogram, Summary};
+use tls::{Connector, SslMode};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(value_enum, value_delimiter = ',')]
     methods: Vec<IngestMethod>,
 
     #[arg(short, long, value_delimiter = ',', default_value = "1000")]
     batch_sizes: Vec<usize>,
 
-    #[arg(short, long, default_value = "false")]
-    transactions: bool,
+    /// Run each worker in a single transaction: on, off, or both as "on,off"
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 0..=1,
+        default_value = "off",
+        default_missing_value = "on",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    transactions: Vec<bool>,
 
+    /// Shorthand for --output csv
     #[arg(short, long, default_value = "false")]
     csv_output: bool,
 
+    /// Results format
+    #[arg(short = 'o', long, value_enum, default_value = "table")]
+    output: OutputFormat,
+
+    /// Also store the run in pgingester_runs/pgingester_results on this database
+    #[arg(long, env = "RESULTS_DB")]
+    results_db: Option<String>,
+
     #[arg(short = 'a', long, default_value = "false")]
     all: bool,
 
-    #[arg(short = 'c', long, env = "CONNECTION_STRING", value_delimiter = ',')]
+    #[arg(long, env = "CONNECTION_STRING", value_delimiter = ',')]
     connection_strings: Vec<String>,
 
     #[arg(short = 'f', long, default_value = "power_generation_1m.csv")]
     input_file: String,
 
-    #[arg(short = 't', long, default_value = "1")]
-    threads: usize,
+    #[command(flatten)]
+    synthetic: synthetic::SyntheticArgs,
+
+    #[command(flatten)]
+    tls: tls::TlsArgs,
+
+    /// Read the input while ingesting instead of loading it into memory first
+    #[arg(long, default_value = "false")]
+    streaming: bool,
+
+    /// Batches buffered per worker between the reader and the workers with --streaming
+    #[arg(long, default_value = "4")]
+    stream_buffer: usize,
+
+    /// Rename CSV header columns to table columns (csv_col=table_col, comma-separated)
+    #[arg(long, value_delimiter = ',', value_parser = input::parse_key_value)]
+    column_map: Vec<(String, String)>,
+
+    /// Ignore the CSV header and load fields in table column order
+    #[arg(long, default_value = "false")]
+    positional: bool,
+
+    /// What to do with input rows that fail to parse
+    #[arg(long, value_enum, default_value = "fail")]
+    on_error: OnError,
+
+    /// Where --on-error quarantine writes rejected rows
+    #[arg(long, default_value = "rejects.csv")]
+    reject_file: String,
+
+    /// Measured runs per method and batch size
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// Unreported runs before the measured ones
+    #[arg(long, default_value = "0")]
+    warmup: u32,
+
+    /// Numbers of concurrent connections (OS threads for the sync engine, tasks for the async engine)
+    #[arg(short = 't', long = "concurrency", visible_alias = "threads", value_delimiter = ',', default_value = "1")]
+    threads: Vec<usize>,
+
+    #[arg(short = 'e', long, value_enum, default_value = "sync")]
+    engine: Engine,
+
+    /// Target table; anything other than power_generation is introspected unless --table-ddl is given
+    #[arg(long, default_value = schema::DEFAULT_TABLE)]
+    table: String,
+
+    /// File with the CREATE TABLE statement to run when the target table doesn't exist
+    #[arg(long)]
+    table_ddl: Option<String>,
+
+    /// Columns of the unique key that the ON CONFLICT methods conflict on
+    #[arg(long, value_delimiter = ',', default_value = "generator_id,timestamp")]
+    conflict_key: Vec<String>,
+
+    /// Fraction of the input (0.0-1.0) inserted before each ON CONFLICT run, so that
+    /// share of the rows conflicts
+    #[arg(long, default_value = "0.0")]
+    conflict_fraction: f64,
+
+    /// Kind of staging table the staged-* methods load first
+    #[arg(long, value_enum, default_value = "temp")]
+    staging_table: StagingTable,
+
+    /// Session setting for every worker connection, as name=value; with several values
+    /// (name=value1,value2) each is benchmarked
+    #[arg(long = "set", value_name = "NAME=VALUES", value_parser = scenario::parse_setting)]
+    settings: Vec<(String, Vec<String>)>,
+
+    /// Run every benchmark over a plain and a TLS connection and report the cost of TLS
+    #[arg(long, default_value = "false")]
+    compare_tls: bool,
+
+    /// TOML file with the benchmark matrix; its keys override the matching options
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compare two results files and fail on throughput regressions
+    Compare(compare::CompareArgs),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Aligned table on the terminal
+    Table,
+    Csv,
+    /// One JSON document with run metadata and all results
+    Json,
+    /// One JSON object per result, each including the run metadata
+    Jsonl,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Engine {
+    /// One blocking postgres::Client per rayon thread
+    Sync,
+    /// One tokio-postgres connection per task on a multi-threaded tokio runtime
+    Async,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum StagingTable {
+    /// Temporary table of the worker's session
+    Temp,
+    /// Unlogged table, one per worker
+    Unlogged,
+}
+
+impl StagingTable {
+    fn keyword(self) -> &'static str {
+        match self {
+            StagingTable::Temp => "TEMP",
+            StagingTable::Unlogged => "UNLOGGED",
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
@@ -86,116 +207,622 @@ enum IngestMethod {
     PreparedInsertUnnest,
     Copy,
     BinaryCopy,
+    PipelinedInsertValues,
+    PipelinedInsertUnnest,
+    InsertValuesDoNothing,
+    InsertValuesDoUpdate,
+    InsertUnnestDoNothing,
+    InsertUnnestDoUpdate,
+    Merge,
+    StagedCopy,
+    StagedBinaryCopy,
+    StagedCopyDoNothing,
+    StagedCopyDoUpdate,
+    StagedBinaryCopyDoNothing,
+    StagedBinaryCopyDoUpdate,
+    CopyFreeze,
+    BinaryCopyFreeze,
 }
 
-#[derive(Debug, Clone)]
-struct BatterySensorData {
-    id: i32,
-    timestamp: DateTime<Utc>,
-    voltage: f64,
-    current: f64,
-    temperature: f64,
-    state_of_charge: f64,
-    internal_resistance: f64,
+impl IngestMethod {
+    /// Name shown in the results
+    fn name(self) -> &'static str {
+        match self {
+            IngestMethod::InsertValues => "Insert VALUES",
+            IngestMethod::PreparedInsertValues => "Prepared Insert VALUES",
+            IngestMethod::InsertUnnest => "UNNEST insert",
+            IngestMethod::PreparedInsertUnnest => "Prepared Insert UNNEST",
+            IngestMethod::Copy => "Copy",
+            IngestMethod::BinaryCopy => "Binary Copy",
+            IngestMethod::PipelinedInsertValues => "Pipelined Insert VALUES",
+            IngestMethod::PipelinedInsertUnnest => "Pipelined Insert UNNEST",
+            IngestMethod::InsertValuesDoNothing => "Insert VALUES ON CONFLICT DO NOTHING",
+            IngestMethod::InsertValuesDoUpdate => "Insert VALUES ON CONFLICT DO UPDATE",
+            IngestMethod::InsertUnnestDoNothing => "UNNEST insert ON CONFLICT DO NOTHING",
+            IngestMethod::InsertUnnestDoUpdate => "UNNEST insert ON CONFLICT DO UPDATE",
+            IngestMethod::Merge => "MERGE UNNEST",
+            IngestMethod::StagedCopy => "Staged Copy",
+            IngestMethod::StagedBinaryCopy => "Staged Binary Copy",
+            IngestMethod::StagedCopyDoNothing => "Staged Copy ON CONFLICT DO NOTHING",
+            IngestMethod::StagedCopyDoUpdate => "Staged Copy ON CONFLICT DO UPDATE",
+            IngestMethod::StagedBinaryCopyDoNothing => "Staged Binary Copy ON CONFLICT DO NOTHING",
+            IngestMethod::StagedBinaryCopyDoUpdate => "Staged Binary Copy ON CONFLICT DO UPDATE",
+            IngestMethod::CopyFreeze => "Copy FREEZE",
+            IngestMethod::BinaryCopyFreeze => "Binary Copy FREEZE",
+        }
+    }
+
+    /// Whether the method truncates the table in its own transaction and loads it with COPY FREEZE
+    fn freezes(self) -> bool {
+        matches!(self, IngestMethod::CopyFreeze | IngestMethod::BinaryCopyFreeze)
+    }
+
+    /// Whether the method loads a staging table and moves its rows with INSERT ... SELECT
+    fn is_staged(self) -> bool {
+        matches!(
+            self,
+            IngestMethod::StagedCopy
+                | IngestMethod::StagedBinaryCopy
+                | IngestMethod::StagedCopyDoNothing
+                | IngestMethod::StagedCopyDoUpdate
+                | IngestMethod::StagedBinaryCopyDoNothing
+                | IngestMethod::StagedBinaryCopyDoUpdate
+        )
+    }
+
+    /// Whether the method needs the unique --conflict-key on the table
+    fn uses_conflict_key(self) -> bool {
+        self.on_conflict().is_some() || self == IngestMethod::Merge
+    }
+
+    /// What the upsert methods do on a key conflict; None for plain inserts
+    fn on_conflict(self) -> Option<ConflictAction> {
+        match self {
+            IngestMethod::InsertValuesDoNothing
+            | IngestMethod::InsertUnnestDoNothing
+            | IngestMethod::StagedCopyDoNothing
+            | IngestMethod::StagedBinaryCopyDoNothing => Some(ConflictAction::DoNothing),
+            IngestMethod::InsertValuesDoUpdate
+            | IngestMethod::InsertUnnestDoUpdate
+            | IngestMethod::StagedCopyDoUpdate
+            | IngestMethod::StagedBinaryCopyDoUpdate => Some(ConflictAction::DoUpdate),
+            _ => None,
+        }
+    }
+}
+
+// Number of statements kept in flight on a pipelined connection
+pub(crate) const PIPELINE_DEPTH: usize = 32;
+
+fn values_params(chunk: &[Row]) -> Vec<&(dyn ToSql + Sync)> {
+    chunk.iter().flat_map(|row| row.iter().map(|value| value as &(dyn ToSql + Sync))).collect()
+}
+
+fn unnest_params<'a>(columns: &'a [ColumnArray<'a>]) -> Vec<&'a (dyn ToSql + Sync)> {
+    columns.iter().map(|column| column as &(dyn ToSql + Sync)).collect()
 }
 
 #[derive(Debug)]
 struct BenchmarkResult {
     connection_name: String,
+    table: String,
     method: String,
     batch_size: usize,
     transaction: bool,
+    // Session settings the workers ran with
+    settings: Vec<(String, String)>,
+    // Whether the workers connected over TLS
+    tls: bool,
+    // Rows/sec lost against the same benchmark without TLS, in percent (--compare-tls)
+    tls_cost: Option<f64>,
+    // Time per worker in each phase of the staging methods
+    stages: Option<StageTimes>,
+    // Visibility map after the last iteration of the COPY FREEZE methods
+    frozen_pages: Option<FrozenPages>,
     duration: std::time::Duration,
+    // Table preparation and connection time before the workers start, not part of duration
+    setup: std::time::Duration,
     rows_per_sec: f64,
     threads: usize,
+    rows: usize,
+    iterations: usize,
+    // Rows/sec across iterations; rows_per_sec and duration are the means
+    throughput: Summary,
+    // Batch latencies of every worker over all measured iterations
+    latency: LatencyHistogram,
+    // Server-side work per iteration (mean)
+    server: ServerMetrics,
+}
+
+// Settings shared by every ingest function for one benchmark run
+#[derive(Debug, Clone)]
+struct IngestContext {
+    conn_info: ConnectionInfo,
+    schema: Arc<TableSchema>,
+    transactions: bool,
+    batch_size: usize,
+    threads: usize,
+    // Session settings applied on every worker connection
+    settings: Vec<(String, String)>,
+    tls: bool,
+    // Unique key of the ON CONFLICT methods and MERGE
+    conflict_key: Vec<String>,
+    // server_version_num of the connection
+    server_version: i32,
+    staging_table: StagingTable,
+}
+
+impl IngestContext {
+    /// `INSERT ... VALUES` for `rows` rows, with the ON CONFLICT clause of `method`
+    fn insert_values_sql(&self, method: IngestMethod, rows: usize) -> String {
+        self.schema.insert_values_sql(rows) + &self.on_conflict_sql(method)
+    }
+
+    /// `INSERT ... SELECT * FROM unnest(...)` with the ON CONFLICT clause of `method`, or
+    /// `MERGE ... USING unnest(...)`
+    fn unnest_sql(&self, method: IngestMethod) -> String {
+        match method {
+            IngestMethod::Merge => self.schema.merge_sql(&self.conflict_key),
+            _ => self.schema.unnest_sql() + &self.on_conflict_sql(method),
+        }
+    }
+
+    /// `INSERT ... SELECT` moving the rows of `staging`; the ON CONFLICT methods take one
+    /// row per key so a batch can't conflict with itself
+    fn move_staged_sql(&self, method: IngestMethod, staging: &str) -> String {
+        let distinct_key = method.on_conflict().map(|_| self.conflict_key.as_slice());
+        self.schema.insert_select_sql(staging, distinct_key) + &self.on_conflict_sql(method)
+    }
+
+    /// Name of `method` in the results; staging methods include the kind of staging table
+    fn method_name(&self, method: IngestMethod) -> String {
+        if method.is_staged() {
+            format!("{} ({})", method.name(), self.staging_table.keyword())
+        } else {
+            method.name().to_string()
+        }
+    }
+
+    fn on_conflict_sql(&self, method: IngestMethod) -> String {
+        method
+            .on_conflict()
+            .map_or(String::new(), |action| self.schema.on_conflict_sql(&self.conflict_key, action))
+    }
+}
+
+// Holds the workers of one run until all of them are connected, and records when
+// they were let go; the ingest timer starts there
+struct StartLine {
+    barrier: std::sync::Barrier,
+    released: std::sync::OnceLock<std::time::Instant>,
+}
+
+impl StartLine {
+    fn new(workers: usize) -> Self {
+        StartLine {
+            barrier: std::sync::Barrier::new(workers),
+            released: std::sync::OnceLock::new(),
+        }
+    }
+
+    fn wait(&self) {
+        if self.barrier.wait().is_leader() {
+            let _ = self.released.set(std::time::Instant::now());
+        }
+    }
+}
+
+// Time a staging method spent loading its staging table, and moving the rows on to
+// the target table (including emptying the staging table for the next batch)
+#[derive(Debug, Clone, Copy, Default)]
+struct StageTimes {
+    copy: std::time::Duration,
+    insert: std::time::Duration,
+}
+
+impl StageTimes {
+    // None if there are no times, i.e. for the other methods
+    fn mean(times: impl Iterator<Item = StageTimes>) -> Option<StageTimes> {
+        let times: Vec<StageTimes> = times.collect();
+        let count = u32::try_from(times.len()).ok().filter(|count| *count > 0)?;
+        Some(StageTimes {
+            copy: times.iter().map(|t| t.copy).sum::<std::time::Duration>() / count,
+            insert: times.iter().map(|t| t.insert).sum::<std::time::Duration>() / count,
+        })
+    }
+}
+
+// One measured run of a method, as seen from main
+struct Run {
+    // Truncating the table and connecting every worker
+    setup: std::time::Duration,
+    duration: std::time::Duration,
+    rows: usize,
+    server: ServerMetrics,
+    // Mean over the workers
+    stages: Option<StageTimes>,
+    frozen_pages: Option<FrozenPages>,
 }
 
-fn truncate_table(client: &mut Client) -> Result<(), Box<dyn Error + Send + Sync>> {
-    client.simple_query("
-        CREATE TABLE IF NOT EXISTS power_generation (
-            generator_id INTEGER,               -- Unique identifier for the generator or energy source
-            timestamp TIMESTAMP WITH TIME ZONE, -- Timestamp of the reading
-            power_output_kw DOUBLE PRECISION,   -- Real-time power output in kilowatts (kW)
-            voltage DOUBLE PRECISION,           -- Voltage in volts (V)
-            current DOUBLE PRECISION,           -- Current in amperes (A)
-            frequency DOUBLE PRECISION,         -- Electrical frequency in hertz (Hz)
-            temperature DOUBLE PRECISION        -- Equipment temperature in degrees Celsius (°C)
-        );
-    ")?;
-    client.simple_query("TRUNCATE TABLE power_generation")?;
-    client.simple_query("ALTER TABLE power_generation SET ( autovacuum_enabled = false);")?;
+// With a `key` the table gets the unique constraint the ON CONFLICT methods need;
+// without one that constraint is dropped so it doesn't slow down plain inserts
+fn truncate_table(client: &mut Client, schema: &TableSchema, key: Option<&[String]>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    client.simple_query(&format!("TRUNCATE TABLE {}", schema.table))?;
+    let constraint = schema::quote_ident(&schema.conflict_constraint());
+    client.simple_query(&format!("ALTER TABLE {} DROP CONSTRAINT IF EXISTS {}", schema.table, constraint))?;
+    if let Some(key) = key {
+        let columns: Vec<String> = key.iter().map(|c| schema::quote_ident(c)).collect();
+        client.simple_query(&format!(
+            "ALTER TABLE {} ADD CONSTRAINT {} UNIQUE ({})",
+            schema.table,
+            constraint,
+            columns.join(", ")
+        ))?;
+    }
+    client.simple_query(&format!("ALTER TABLE {} SET ( autovacuum_enabled = false);", schema.table))?;
     client.simple_query("CHECKPOINT")?;
     Ok(())
 }
 
+// Inserts an evenly spread `fraction` of `records` so that share of a run conflicts
+fn seed_conflicts(client: &mut Client, schema: &TableSchema, records: &[Row], fraction: f64) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let types = schema.types();
+    let writer = client.copy_in(&schema.binary_copy_sql())?;
+    let mut writer = BinaryCopyInWriter::new(writer, &types);
+    for (i, record) in records.iter().enumerate() {
+        // Row i is seeded whenever the number of rows to seed so far goes up
+        if ((i + 1) as f64 * fraction).floor() > (i as f64 * fraction).floor() {
+            writer.write_raw(record.iter())?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
 fn create_benchmark_result(
-    connection_name: &str,
-    method: &str, 
-    duration: std::time::Duration, 
-    rows_per_sec: f64, 
-    transactions: bool, 
-    batch_size: usize,
-    threads: usize,
+    ctx: &IngestContext,
+    method: &str,
+    duration: std::time::Duration,
+    rows: usize,
+    latency: LatencyHistogram,
 ) -> BenchmarkResult {
+    let run = Run { setup: std::time::Duration::ZERO, duration, rows, server: ServerMetrics::default(), stages: None, frozen_pages: None };
+    summarize_runs(ctx, method, &[run], latency)
+}
+
+// Zero-duration result of a method skipped for these settings, filtered out before reporting
+fn skipped_result(ctx: &IngestContext, method: &str) -> BenchmarkResult {
+    create_benchmark_result(ctx, method, std::time::Duration::ZERO, 0, LatencyHistogram::default())
+}
+
+// Combines the (duration, rows) of each measured iteration into one result
+fn summarize_runs(
+    ctx: &IngestContext,
+    method: &str,
+    runs: &[Run],
+    latency: LatencyHistogram,
+) -> BenchmarkResult {
+    let throughput: Vec<f64> = runs
+        .iter()
+        .map(|run| if run.duration.is_zero() { 0.0 } else { run.rows as f64 / run.duration.as_secs_f64() })
+        .collect();
+    let throughput = Summary::of(&throughput);
+    let total: std::time::Duration = runs.iter().map(|run| run.duration).sum();
+    let setup: std::time::Duration = runs.iter().map(|run| run.setup).sum();
+    let server: Vec<ServerMetrics> = runs.iter().map(|run| run.server).collect();
     BenchmarkResult {
-        connection_name: connection_name.to_string(),
+        connection_name: ctx.conn_info.name.clone(),
+        table: ctx.schema.table.clone(),
         method: method.to_string(),
-        batch_size,
-        transaction: transactions,
-        duration,
-        rows_per_sec,
-        threads,
+        batch_size: ctx.batch_size,
+        transaction: ctx.transactions,
+        settings: ctx.settings.clone(),
+        tls: ctx.tls,
+        tls_cost: None,
+        stages: StageTimes::mean(runs.iter().filter_map(|run| run.stages)),
+        frozen_pages: runs.last().and_then(|run| run.frozen_pages),
+        duration: total / runs.len().max(1) as u32,
+        setup: setup / runs.len().max(1) as u32,
+        rows_per_sec: throughput.mean,
+        threads: ctx.threads,
+        rows: runs.last().map_or(0, |run| run.rows),
+        iterations: runs.len(),
+        throughput,
+        latency,
+        server: ServerMetrics::mean(&server),
+    }
+}
+
+// Zero-duration result for a method that can't run with these settings, or None if it can
+fn unsupported(ctx: &IngestContext, method: IngestMethod) -> Option<BenchmarkResult> {
+    match method {
+        IngestMethod::InsertValues
+        | IngestMethod::PreparedInsertValues
+        | IngestMethod::PipelinedInsertValues
+        | IngestMethod::InsertValuesDoNothing
+        | IngestMethod::InsertValuesDoUpdate
+            if ctx.batch_size > ctx.schema.max_values_batch() =>
+        {
+            Some(too_many_parameters(ctx, method.name()))
+        }
+        IngestMethod::InsertUnnest
+        | IngestMethod::PreparedInsertUnnest
+        | IngestMethod::PipelinedInsertUnnest
+        | IngestMethod::InsertUnnestDoNothing
+        | IngestMethod::InsertUnnestDoUpdate
+        | IngestMethod::Merge
+            if ctx.schema.has_array_columns() =>
+        {
+            Some(array_columns_unsupported(ctx, method.name()))
+        }
+        IngestMethod::Merge if ctx.server_version < 150000 => {
+            eprintln!(
+                "{} skipped, MERGE needs PostgreSQL 15 or later and {} runs {}",
+                method.name(),
+                ctx.conn_info.name,
+                ctx.server_version / 10000
+            );
+            Some(skipped_result(ctx, method.name()))
+        }
+        IngestMethod::CopyFreeze | IngestMethod::BinaryCopyFreeze if ctx.threads > 1 => {
+            eprintln!("{} skipped, COPY FREEZE only works on a single connection", method.name());
+            Some(skipped_result(ctx, method.name()))
+        }
+        IngestMethod::CopyFreeze | IngestMethod::BinaryCopyFreeze if !ctx.transactions => {
+            eprintln!("{} skipped, COPY FREEZE needs the TRUNCATE in the same transaction, use --transactions on", method.name());
+            Some(skipped_result(ctx, method.name()))
+        }
+        _ if method.uses_conflict_key() => conflict_key_unsupported(ctx, method),
+        _ => None,
+    }
+}
+
+// Result recorded for upserts that can't use --conflict-key on this table, or None if they can
+fn conflict_key_unsupported(ctx: &IngestContext, method: IngestMethod) -> Option<BenchmarkResult> {
+    let columns = &ctx.schema.columns;
+    let updates = method.on_conflict() == Some(ConflictAction::DoUpdate);
+    let reason = match ctx.conflict_key.iter().find(|key| !columns.iter().any(|c| &c.name == *key)) {
+        Some(missing) => format!("table {} has no conflict key column {}", ctx.schema.table, missing),
+        None if updates && columns.iter().all(|c| ctx.conflict_key.contains(&c.name)) => {
+            format!("every column of {} is in the conflict key, nothing to update", ctx.schema.table)
+        }
+        None => return None,
+    };
+    eprintln!("{} skipped, {}", method.name(), reason);
+    Some(skipped_result(ctx, method.name()))
+}
+
+// Result recorded for VALUES methods whose batch would exceed the bind parameter limit
+fn too_many_parameters(ctx: &IngestContext, method: &str) -> BenchmarkResult {
+    eprintln!("{} with batch size of {} failed, too many parameters", method, ctx.batch_size);
+    skipped_result(ctx, method)
+}
+
+// Result recorded for UNNEST methods on tables with array columns, which unnest() would flatten
+fn array_columns_unsupported(ctx: &IngestContext, method: &str) -> BenchmarkResult {
+    eprintln!("{} skipped, table {} has array columns", method, ctx.schema.table);
+    skipped_result(ctx, method)
+}
+
+// Fills in tls_cost of every TLS result from the matching plain one
+fn tls_costs(results: &mut [BenchmarkResult]) {
+    let key = |r: &BenchmarkResult| {
+        (r.connection_name.clone(), r.table.clone(), r.method.clone(), r.batch_size, r.transaction, r.threads, r.settings.clone())
+    };
+    let plain: std::collections::HashMap<_, f64> =
+        results.iter().filter(|r| !r.tls).map(|r| (key(r), r.rows_per_sec)).collect();
+    for result in results.iter_mut().filter(|r| r.tls) {
+        result.tls_cost = plain
+            .get(&key(result))
+            .filter(|rows_per_sec| **rows_per_sec > 0.0)
+            .map(|rows_per_sec| (rows_per_sec - result.rows_per_sec) / rows_per_sec * 100.0);
+    }
+}
+
+pub(crate) const LATENCY_PERCENTILES: [(&str, f64); 4] = [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p99.9", 99.9)];
+
+// Batch latency at each of LATENCY_PERCENTILES, then the maximum
+fn latency_columns(result: &BenchmarkResult) -> Vec<String> {
+    LATENCY_PERCENTILES
+        .iter()
+        .map(|(_, percentile)| result.latency.percentile(*percentile))
+        .chain(std::iter::once(result.latency.max()))
+        .map(|latency| format!("{:.2?}", latency))
+        .collect()
+}
+
+// Server metrics as WAL bytes, WAL records, full-page images, buffers written, table size
+fn server_columns(result: &BenchmarkResult, human: bool) -> Vec<String> {
+    let server = &result.server;
+    let bytes = |value: i64| if human { format_bytes(value) } else { value.to_string() };
+    let optional = |value: Option<i64>| value.map_or(if human { "-".to_string() } else { String::new() }, |v| v.to_string());
+    vec![
+        bytes(server.wal_bytes),
+        optional(server.wal_records),
+        optional(server.wal_fpi),
+        server.buffers_written.to_string(),
+        bytes(server.table_size),
+    ]
+}
+
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
+
+// Names of the session settings of any result, in the order they were given
+fn setting_names(results: &[BenchmarkResult]) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    for (name, _) in results.iter().flat_map(|r| &r.settings) {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
     }
+    names
 }
 
-fn print_results(results: &[BenchmarkResult], csv_output: bool, total_records: usize) {
+fn setting_value<'a>(result: &'a BenchmarkResult, name: &str) -> &'a str {
+    result.settings.iter().find(|(n, _)| n == name).map_or("", |(_, value)| value)
+}
+
+fn print_results(results: &[BenchmarkResult], csv_output: bool, total_records: usize, rejected_records: usize) {
+    let settings = setting_names(results);
     let max_speed = results.iter()
         .map(|r| r.rows_per_sec)
         .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
         .unwrap_or(1.0);
 
     if csv_output {
-        println!("Connection,Method,Batch Size,Transaction,Threads,Duration,Rows/sec,Relative Speed");
+        if rejected_records > 0 {
+            // Keep stdout parseable as CSV
+            eprintln!("{} input rows rejected", rejected_records);
+        }
+        // Setting columns are prefixed so they can't be mistaken for the others
+        let setting_headers: String = settings.iter().map(|name| format!("set:{},", name)).collect();
+        println!("Connection,Table,Method,Batch Size,Transaction,TLS,Threads,{}Iterations,Setup,Duration,Rows/sec,TLS Cost,Stage Copy,Stage Insert,Frozen Pages,Table Pages,Min Rows/sec,Median Rows/sec,P95 Rows/sec,Max Rows/sec,Stddev Rows/sec,Batch p50,Batch p90,Batch p99,Batch p99.9,Batch Max,WAL Bytes,WAL Records,WAL FPI,Buffers Written,Table Size,Relative Speed", setting_headers);
         for result in results {
-            println!("{},{},{},{},{},{:.2?},{:.0},x{:.2}",
+            let setting_values: String = settings.iter().map(|name| format!("{},", setting_value(result, name))).collect();
+            println!("{},{},{},{},{},{},{},{}{},{:.2?},{:.2?},{:.0},{},{},{},{},{},{:.0},{:.0},{:.0},{:.0},{:.0},{},{},x{:.2}",
                 result.connection_name,
+                result.table,
                 result.method,
                 result.batch_size,
                 if result.transaction { "Yes" } else { "No" },
+                if result.tls { "Yes" } else { "No" },
                 result.threads,
+                setting_values,
+                result.iterations,
+                result.setup,
                 result.duration,
                 result.rows_per_sec,
+                result.tls_cost.map_or(String::new(), |cost| format!("{:.1}%", cost)),
+                result.stages.map_or(String::new(), |stages| format!("{:.2?}", stages.copy)),
+                result.stages.map_or(String::new(), |stages| format!("{:.2?}", stages.insert)),
+                result.frozen_pages.map_or(String::new(), |frozen| frozen.all_frozen.to_string()),
+                result.frozen_pages.map_or(String::new(), |frozen| frozen.pages.to_string()),
+                result.throughput.min,
+                result.throughput.median,
+                result.throughput.p95,
+                result.throughput.max,
+                result.throughput.stddev,
+                latency_columns(result).join(","),
+                server_columns(result, false).join(","),
                 max_speed / result.rows_per_sec
             );
         }
     } else {
         println!();
-        println!("\x1B[1m Results for import of {} records\x1B[0m", total_records);
+        if rejected_records > 0 {
+            println!("\x1B[1m Results for import of {} records ({} rejected input rows)\x1B[0m", total_records, rejected_records);
+        } else {
+            println!("\x1B[1m Results for import of {} records\x1B[0m", total_records);
+        }
 
         let mut table = Table::new();
         table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-        table.add_row(row![
-            b->"Connection",
-            b->"Method",
-            b->"Batch Size",
-            b->"Transaction",
-            b->"Threads",
-            b->"Duration",
-            b->"Rows/sec",
-            b->"Relative Speed"
-        ]);
+        // Spread columns only mean something with more than one measured run
+        let repeated = results.iter().any(|r| r.iterations > 1);
+        let several_tables = results.iter().any(|r| r.table != results[0].table);
+        let mixed_tls = results.iter().any(|r| r.tls != results[0].tls);
+        let tls_compared = results.iter().any(|r| r.tls_cost.is_some());
+        let staged = results.iter().any(|r| r.stages.is_some());
+        let frozen = results.iter().any(|r| r.frozen_pages.is_some());
+        let mut header = row![b->"Connection"];
+        if several_tables {
+            header.add_cell(prettytable::cell!(b->"Table"));
+        }
+        let mut titles = vec!["Method", "Batch Size", "Transaction"];
+        if mixed_tls {
+            titles.push("TLS");
+        }
+        titles.push("Threads");
+        titles.extend(&settings);
+        titles.extend(["Setup", "Duration", "Rows/sec"]);
+        if tls_compared {
+            titles.push("TLS Cost");
+        }
+        if staged {
+            titles.extend(["Stage Copy", "Stage Insert"]);
+        }
+        if frozen {
+            titles.push("Frozen Pages");
+        }
+        for title in titles {
+            header.add_cell(prettytable::cell!(b->title));
+        }
+        if repeated {
+            for title in ["Iterations", "Min", "Median", "P95", "Max", "Stddev"] {
+                header.add_cell(prettytable::cell!(b->title));
+            }
+        }
+        for (name, _) in LATENCY_PERCENTILES {
+            header.add_cell(prettytable::cell!(b->format!("Batch {}", name)));
+        }
+        header.add_cell(prettytable::cell!(b->"Batch Max"));
+        for title in ["WAL", "WAL Records", "FPI", "Buffers Written", "Table Size"] {
+            header.add_cell(prettytable::cell!(b->title));
+        }
+        header.add_cell(prettytable::cell!(b->"Relative Speed"));
+        table.add_row(header);
         
         for result in results {
-            table.add_row(row![
-                result.connection_name,
-                result.method,
+            let mut row = row![result.connection_name];
+            if several_tables {
+                row.add_cell(prettytable::cell!(result.table));
+            }
+            let mut values = vec![
+                result.method.clone(),
                 format!("{}", result.batch_size),
-                if result.transaction { "Yes" } else { "No" },
-                format!("{}", result.threads),
+                (if result.transaction { "Yes" } else { "No" }).to_string(),
+            ];
+            if mixed_tls {
+                values.push((if result.tls { "Yes" } else { "No" }).to_string());
+            }
+            values.push(format!("{}", result.threads));
+            values.extend(settings.iter().map(|name| setting_value(result, name).to_string()));
+            values.extend([
+                format!("{:.2?}", result.setup),
                 format!("{:.2?}", result.duration),
                 format!("{:.0}", result.rows_per_sec),
-                format!("x{:.2}", max_speed / result.rows_per_sec)
             ]);
+            if tls_compared {
+                values.push(result.tls_cost.map_or(String::new(), |cost| format!("{:.1}%", cost)));
+            }
+            if staged {
+                values.push(result.stages.map_or(String::new(), |stages| format!("{:.2?}", stages.copy)));
+                values.push(result.stages.map_or(String::new(), |stages| format!("{:.2?}", stages.insert)));
+            }
+            if frozen {
+                values.push(
+                    result.frozen_pages.map_or(String::new(), |frozen| format!("{} / {}", frozen.all_frozen, frozen.pages)),
+                );
+            }
+            for value in values {
+                row.add_cell(prettytable::cell!(value));
+            }
+            if repeated {
+                row.add_cell(prettytable::cell!(result.iterations));
+                for value in [
+                    result.throughput.min,
+                    result.throughput.median,
+                    result.throughput.p95,
+                    result.throughput.max,
+                    result.throughput.stddev,
+                ] {
+                    row.add_cell(prettytable::cell!(format!("{:.0}", value)));
+                }
+            }
+            for value in latency_columns(result).into_iter().chain(server_columns(result, true)) {
+                row.add_cell(prettytable::cell!(value));
+            }
+            row.add_cell(prettytable::cell!(format!("x{:.2}", max_speed / result.rows_per_sec)));
+            table.add_row(row);
         }
         
         table.printstd();
@@ -204,440 +831,655 @@ fn print_results(results: &[BenchmarkResult], csv_output: bool, total_records: u
 
 fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let cli = Cli::parse();
+    if let Some(Command::Compare(args)) = &cli.command {
+        return compare::run(args);
+    }
+    let started_at = chrono::Utc::now();
     
-    // Add SSL connector setup
-    let mut builder = SslConnector::builder(SslMethod::tls())?;
-    builder.set_verify(SslVerifyMode::NONE);
-    let connector = MakeTlsConnector::new(builder.build());
-
-    // Parse connection strings
-    let connections: Vec<ConnectionInfo> = if cli.connection_strings.is_empty() {
-        // Default to environment variable if no connections specified
-        vec![ConnectionInfo::from_str(
-            &env::var("CONNECTION_STRING")
-                .expect("CONNECTION_STRING must be provided via argument or environment variable"),
-            1
-        )?]
+    let matrix = Matrix {
+        connections: cli.connection_strings.clone(),
+        methods: if cli.all {
+            vec![
+                IngestMethod::InsertValues,
+                IngestMethod::PreparedInsertValues,
+                IngestMethod::InsertUnnest,
+                IngestMethod::PreparedInsertUnnest,
+                IngestMethod::Copy,
+                IngestMethod::BinaryCopy,
+                IngestMethod::PipelinedInsertValues,
+                IngestMethod::PipelinedInsertUnnest,
+                IngestMethod::InsertValuesDoNothing,
+                IngestMethod::InsertValuesDoUpdate,
+                IngestMethod::InsertUnnestDoNothing,
+                IngestMethod::InsertUnnestDoUpdate,
+                IngestMethod::Merge,
+                IngestMethod::StagedCopy,
+                IngestMethod::StagedBinaryCopy,
+                IngestMethod::StagedCopyDoNothing,
+                IngestMethod::StagedCopyDoUpdate,
+                IngestMethod::StagedBinaryCopyDoNothing,
+                IngestMethod::StagedBinaryCopyDoUpdate,
+                IngestMethod::CopyFreeze,
+                IngestMethod::BinaryCopyFreeze,
+            ]
+        } else {
+            cli.methods.clone()
+        },
+        batch_sizes: cli.batch_sizes.clone(),
+        threads: cli.threads.clone(),
+        transactions: cli.transactions.clone(),
+        tables: vec![TableVariant { table: cli.table.clone(), ddl: cli.table_ddl.as_ref().map(PathBuf::from) }],
+        settings: cli.settings.clone(),
+        iterations: cli.iterations,
+        warmup: cli.warmup,
+    };
+    let (matrix, scenario) = match &cli.scenario {
+        Some(path) => {
+            let (matrix, scenario) = scenario::load(path, matrix)?;
+            (matrix, Some(scenario))
+        }
+        None => (matrix, None),
+    };
+    matrix.validate()?;
+    if !(0.0..=1.0).contains(&cli.conflict_fraction) {
+        return Err("--conflict-fraction must be between 0.0 and 1.0".into());
+    }
+    if cli.conflict_fraction > 0.0 && cli.streaming {
+        return Err("--conflict-fraction seeds the table from the loaded input and can't be used with --streaming".into());
+    }
+
+    // Parse connection strings; without any, everything comes from PGSERVICE and the PG* variables
+    let tls_params = cli.tls.params();
+    let connections: Vec<ConnectionInfo> = if matrix.connections.is_empty() {
+        vec![ConnectionInfo::parse("", 0, &tls_params)?]
     } else {
-        cli.connection_strings
+        matrix.connections
             .iter()
             .enumerate()
-            .map(|(i, s)| ConnectionInfo::from_str(s, i))
+            .map(|(i, s)| ConnectionInfo::parse(s, i, &tls_params))
             .collect::<Result<Vec<_>, _>>()?
     };
 
-    let records: Vec<BatterySensorData> = read_csv(&cli.input_file)?;
     let mut results: Vec<BenchmarkResult> = Vec::new();
 
-    // Set up thread pool once
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(cli.threads)
-        .build_global()
-        .unwrap();
+    // The async runtime is shared by all benchmarks; sync runs get a rayon pool per thread count
+    let runtime = match cli.engine {
+        Engine::Sync => None,
+        Engine::Async => Some(
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?,
+        ),
+    };
+
+    let table_ddls = matrix
+        .tables
+        .iter()
+        .map(|variant| match &variant.ddl {
+            Some(path) => std::fs::read_to_string(path)
+                .map(Some)
+                .map_err(|e| format!("Can't read {}: {}", path.display(), e)),
+            None if variant.table == schema::DEFAULT_TABLE => Ok(Some(schema::POWER_GENERATION_DDL.to_string())),
+            None => Ok(None),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let connectors = connections
+        .iter()
+        .map(|conn_info| {
+            Connector::new(&conn_info.tls).map_err(|e| format!("TLS setup for {} failed: {}", conn_info.name, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Load the target tables on every connection; the input is parsed for the first one
+    let mut clients = Vec::with_capacity(connections.len());
+    let mut encrypted = Vec::with_capacity(connections.len());
+    let mut servers = Vec::with_capacity(connections.len());
+    let mut schemas: Vec<Vec<Arc<TableSchema>>> = Vec::with_capacity(connections.len());
+    for (conn_info, connector) in connections.iter().zip(&connectors) {
+        let mut client = connector.connect(&conn_info.connection_string)?;
+        let mut tables = Vec::with_capacity(matrix.tables.len());
+        for (i, (variant, ddl)) in matrix.tables.iter().zip(&table_ddls).enumerate() {
+            let schema = TableSchema::load(&mut client, &variant.table, ddl.as_deref())?;
+            row::check_supported(&schema)?;
+            if let Some(first) = schemas.first() {
+                if first[i].types() != schema.types() {
+                    return Err(format!(
+                        "Table {} on {} has different column types than on {}",
+                        schema.table, conn_info.name, connections[0].name
+                    ).into());
+                }
+            }
+            tables.push(Arc::new(schema));
+        }
+        servers.push(report::ServerInfo::fetch(&mut client, &conn_info.name)?);
+        encrypted.push(tls::is_encrypted(&mut client)?);
+        clients.push(client);
+        schemas.push(tables);
+    }
+
+    let mapping = ColumnMapping {
+        renames: cli.column_map.clone(),
+        positional: cli.positional,
+    };
+    // Every table variant gets the input converted to its own column types, and its own reject file
+    let reject_files: Vec<String> = schemas[0]
+        .iter()
+        .map(|schema| input::reject_file_for(&cli.reject_file, &schema.name, schemas[0].len() > 1))
+        .collect();
+    let mut rejects = Vec::with_capacity(reject_files.len());
+    let mut records: Vec<Arc<[Row]>> = Vec::with_capacity(matrix.tables.len());
+    for (schema, reject_file) in schemas[0].iter().zip(&reject_files) {
+        let mut table_rejects = Rejects::new(cli.on_error, reject_file)?;
+        records.push(match cli.synthetic.synthetic {
+            // Streaming runs read the input again for every benchmark instead
+            _ if cli.streaming => Vec::new().into(),
+            Some(rows) => synthetic::generate(schema, &cli.synthetic, rows).into(),
+            None => input::read_csv(&cli.input_file, schema, &mapping, &mut table_rejects)?.into(),
+        });
+        rejects.push(table_rejects);
+    }
 
     // For each connection
-    for conn_info in &connections {
+    let setting_combinations = matrix.setting_combinations();
+    let targets = connections.iter().zip(connectors).zip(encrypted).zip(clients).zip(schemas);
+    for ((((conn_info, connector), encrypted), mut client), tables) in targets {
         eprintln!("Testing connection: {}", conn_info.name);
-        
-        let methods = if cli.all {
+        let server_version = server_stats::server_version_num(&mut client)?;
+
+        // With --compare-tls every benchmark runs over a plain and then over a TLS connection
+        let variants = if cli.compare_tls {
+            let mode = match conn_info.tls.mode {
+                SslMode::VerifyCa | SslMode::VerifyFull => conn_info.tls.mode,
+                SslMode::Disable | SslMode::Prefer | SslMode::Require => SslMode::Require,
+            };
+            let with_tls = conn_info.with_sslmode(mode);
             vec![
-                IngestMethod::InsertValues,
-                IngestMethod::PreparedInsertValues,
-                IngestMethod::InsertUnnest,
-                IngestMethod::PreparedInsertUnnest,
-                IngestMethod::Copy,
-                IngestMethod::BinaryCopy,
+                (conn_info.with_sslmode(SslMode::Disable), Connector::NoTls, false),
+                (with_tls.clone(), Connector::new(&with_tls.tls)?, true),
             ]
         } else {
-            cli.methods.clone()
+            vec![(conn_info.clone(), connector, encrypted)]
         };
 
-        // Run benchmarks for this connection
-        for batch_size in &cli.batch_sizes {
-            for method in &methods {
-                let chunk_size = records.len() / cli.threads;
-                let mut client = Client::connect(&conn_info.connection_string, connector.clone())?;
-                truncate_table(&mut client)?;  // Ensure table is clean before parallel insert
-
-                let start = std::time::Instant::now();
-                let thread_results = (0..cli.threads).into_par_iter().map(|i| {
-                    let start_idx = i * chunk_size;
-                    let end_idx = if i == cli.threads - 1 { records.len() } else { (i + 1) * chunk_size };
-                    let thread_records = &records[start_idx..end_idx];
-                    
-                    let mut thread_client = Client::connect(&conn_info.connection_string, connector.clone())?;
-                    match method {
-                        IngestMethod::BinaryCopy => binary_copy(&mut thread_client, thread_records, cli.transactions, *batch_size, conn_info, cli.threads),
-                        IngestMethod::InsertValues => insert_values(&mut thread_client, thread_records, cli.transactions, *batch_size, conn_info, cli.threads),
-                        IngestMethod::PreparedInsertValues => prepared_insert_values(&mut thread_client, thread_records, cli.transactions, *batch_size, conn_info, cli.threads),
-                        IngestMethod::InsertUnnest => insert_unnest(&mut thread_client, thread_records, cli.transactions, *batch_size, conn_info, cli.threads),
-                        IngestMethod::PreparedInsertUnnest => prepared_insert_unnest(&mut thread_client, thread_records, cli.transactions, *batch_size, conn_info, cli.threads),
-                        IngestMethod::Copy => copy(&mut thread_client, thread_records, cli.transactions, *batch_size, conn_info, cli.threads),
-                    }
-                }).collect::<Result<Vec<_>, _>>()?;
-
-                // Calculate aggregate metrics
-                let duration = start.elapsed();
-                let total_rows = records.len();
-                let rows_per_sec = total_rows as f64 / duration.as_secs_f64();
-                
-                results.push(BenchmarkResult {
-                    connection_name: conn_info.name.clone(),
-                    method: thread_results[0].method.clone(),
-                    batch_size: *batch_size,
-                    transaction: cli.transactions,
-                    duration,
-                    rows_per_sec,
-                    threads: cli.threads,
+        for (table_index, (schema, records)) in tables.iter().zip(&records).enumerate() {
+            for &threads in &matrix.threads {
+                let pool = match runtime {
+                    None => Some(rayon::ThreadPoolBuilder::new().num_threads(threads).build()?),
+                    Some(_) => None,
+                };
+                let (batch_sizes, methods, variants) = (&matrix.batch_sizes, &matrix.methods, &variants);
+                let settings = &setting_combinations;
+                let combinations = matrix.transactions.iter().flat_map(|&transactions| {
+                    settings.iter().flat_map(move |settings| {
+                        batch_sizes.iter().flat_map(move |&batch_size| {
+                            methods.iter().flat_map(move |&method| {
+                                variants.iter().map(move |variant| (transactions, settings, batch_size, method, variant))
+                            })
+                        })
+                    })
                 });
+
+                // Run benchmarks for this connection, table and thread count
+                for (transactions, settings, batch_size, method, (conn_info, connector, tls)) in combinations {
+                    let ctx = IngestContext {
+                        conn_info: conn_info.clone(),
+                        schema: Arc::clone(schema),
+                        transactions,
+                        batch_size,
+                        threads,
+                        settings: settings.clone(),
+                        tls: *tls,
+                        conflict_key: cli.conflict_key.clone(),
+                        server_version,
+                        staging_table: cli.staging_table,
+                    };
+                    if let Some(skipped) = unsupported(&ctx, method) {
+                        results.push(skipped);
+                        continue;
+                    }
+                    let mut runs = Vec::with_capacity(matrix.iterations as usize);
+                    let mut latency = LatencyHistogram::new();
+                    for iteration in 0..matrix.warmup + matrix.iterations {
+                        let setup_start = std::time::Instant::now();
+                        // Ensure table is clean before parallel insert
+                        let conflict_key = method.uses_conflict_key().then_some(ctx.conflict_key.as_slice());
+                        truncate_table(&mut client, schema, conflict_key)?;
+                        if conflict_key.is_some() && cli.conflict_fraction > 0.0 {
+                            seed_conflicts(&mut client, schema, records, cli.conflict_fraction)?;
+                        }
+                        let before = server_stats::snapshot(&mut client, server_version)?;
+
+                        let (thread_results, released) = std::thread::scope(|scope| {
+                            let (inputs, reader) = if cli.streaming {
+                                let (sender, inputs) = batches::streamed(threads, cli.stream_buffer);
+                                let table_rejects = &mut rejects[table_index];
+                                *table_rejects = Rejects::new(cli.on_error, &reject_files[table_index])?;
+                                let rows: Box<dyn Iterator<Item = _> + Send> = match cli.synthetic.synthetic {
+                                    Some(rows) => Box::new(synthetic::Generator::new(schema, &cli.synthetic).take(rows).map(Ok)),
+                                    None => Box::new(CsvRows::open(&cli.input_file, schema, &mapping, table_rejects)?),
+                                };
+                                let reader = scope.spawn(move || batches::feed(rows, batch_size, sender));
+                                (inputs, Some(reader))
+                            } else {
+                                (batches::preloaded(records, threads), None)
+                            };
+
+                            let thread_results = match (&runtime, &pool) {
+                                (Some(runtime), _) => async_engine::run(runtime, connector, method, inputs, &ctx),
+                                (None, Some(pool)) => run_sync(pool, method, connector, inputs, &ctx),
+                                (None, None) => unreachable!("sync runs always have a pool"),
+                            };
+                            let reader_result = reader.map(|reader| reader.join().expect("input reader panicked"));
+                            let thread_results = thread_results?;
+                            // A reader error ends the stream early, so it outranks the workers' results
+                            if let Some(reader_result) = reader_result {
+                                reader_result?;
+                            }
+                            Ok::<_, Box<dyn Error + Send + Sync>>(thread_results)
+                        })?;
+
+                        let mut run = Run {
+                            setup: released - setup_start,
+                            duration: released.elapsed(),
+                            rows: thread_results.iter().map(|r| r.rows).sum(),
+                            server: server_stats::measure(&mut client, server_version, schema, &before)?,
+                            stages: StageTimes::mean(thread_results.iter().filter_map(|r| r.stages)),
+                            frozen_pages: None,
+                        };
+                        if method.freezes() {
+                            match server_stats::frozen_pages(&mut client, schema) {
+                                Ok(Some(frozen)) => {
+                                    if frozen.all_frozen < frozen.pages {
+                                        eprintln!(
+                                            "{}: only {} of {} pages are all-frozen, COPY FREEZE did not freeze the table",
+                                            method.name(), frozen.all_frozen, frozen.pages
+                                        );
+                                    }
+                                    run.frozen_pages = Some(frozen);
+                                }
+                                Ok(None) => eprintln!(
+                                    "{}: pg_visibility is not installed, so the frozen pages are not checked",
+                                    method.name()
+                                ),
+                                Err(e) => eprintln!("Can't check frozen pages with pg_visibility: {}", e),
+                            }
+                        }
+                        // Warmup runs only prime caches and are not reported
+                        if iteration >= matrix.warmup {
+                            runs.push(run);
+                            for thread_result in &thread_results {
+                                latency.merge(&thread_result.latency);
+                            }
+                        }
+                    }
+                    results.push(summarize_runs(&ctx, &ctx.method_name(method), &runs, latency));
+                }
             }
         }
     }
     
+    if cli.compare_tls {
+        tls_costs(&mut results);
+    }
     results.sort_by(|a, b| a.rows_per_sec.partial_cmp(&b.rows_per_sec).unwrap_or(std::cmp::Ordering::Equal));
     let valid_results: Vec<_> = results.into_iter().filter(|r| r.duration.as_nanos() > 0).collect();
-    print_results(&valid_results, cli.csv_output, records.len());
+    let total_records = valid_results.iter().map(|r| r.rows).max().unwrap_or(0);
+    let rejected_rows = rejects.iter().map(|rejects| rejects.count).sum();
+    let metadata = report::RunMetadata {
+        started_at,
+        args: env::args().collect(),
+        input: if cli.synthetic.synthetic.is_some() { "synthetic".to_string() } else { cli.input_file.clone() },
+        rows: total_records,
+        rejected_rows,
+        servers,
+        scenario,
+    };
+    if let Some(results_db) = &cli.results_db {
+        let results_db = ConnectionInfo::parse(results_db, 0, &tls_params)?;
+        let run_id = report::save(&results_db.connection_string, &Connector::new(&results_db.tls)?, &valid_results, &metadata)?;
+        eprintln!("Saved results as run {} in the results database", run_id);
+    }
+
+    let output = if cli.csv_output { OutputFormat::Csv } else { cli.output };
+    match output {
+        OutputFormat::Table | OutputFormat::Csv => {
+            print_results(&valid_results, output == OutputFormat::Csv, total_records, rejected_rows)
+        }
+        OutputFormat::Json => report::print_json(&valid_results, &metadata),
+        OutputFormat::Jsonl => report::print_jsonl(&valid_results, &metadata),
+    }
     Ok(())
 }
 
-fn read_csv(path: &str) -> Result<Vec<BatterySensorData>, Box<dyn Error + Send + Sync>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut csv_reader = Reader::from_reader(reader);
-    let mut records = Vec::new();
-
-    for result in csv_reader.records() {
-        let record = result?;
-        records.push(BatterySensorData {
-            id: record[0].parse()?,
-            timestamp: DateTime::parse_from_rfc3339(&record[1]).expect("santa").into(),
-            voltage: record[2].parse()?,
-            current: record[3].parse()?,
-            temperature: record[4].parse()?,
-            state_of_charge: record[5].parse()?,
-            internal_resistance: record[6].parse()?,
-        });
+// Runs one blocking worker per thread of `pool`, all released together once
+// connected. Returns the worker results and the moment they were released.
+fn run_sync(
+    pool: &rayon::ThreadPool,
+    method: IngestMethod,
+    connector: &Connector,
+    inputs: Vec<WorkerInput>,
+    ctx: &IngestContext,
+) -> Result<(Vec<BenchmarkResult>, std::time::Instant), Box<dyn Error + Send + Sync>> {
+    // broadcast runs exactly one closure per pool thread, so every worker reaches the barrier
+    assert_eq!(inputs.len(), pool.current_num_threads());
+    let start_line = StartLine::new(inputs.len());
+    let results = pool.broadcast(|worker| sync_worker(method, connector, &inputs[worker.index()], ctx, &start_line));
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let released = *start_line.released.get().expect("workers passed the start line");
+    Ok((results, released))
+}
+
+fn sync_worker(
+    method: IngestMethod,
+    connector: &Connector,
+    input: &WorkerInput,
+    ctx: &IngestContext,
+    start_line: &StartLine,
+) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>> {
+    let mut batches = input.batches(ctx.batch_size);
+    // Workers wait at the start line even if they failed to connect, so the others aren't stuck
+    if let IngestMethod::PipelinedInsertValues | IngestMethod::PipelinedInsertUnnest = method {
+        let connected = pipelined_client(connector, ctx);
+        start_line.wait();
+        let (runtime, client) = connected?;
+        return runtime.block_on(async_engine::ingest(method, &client, &mut batches, ctx));
     }
 
-    Ok(records)
+    let connected = connector
+        .connect(&ctx.conn_info.connection_string)
+        .map_err(Into::into)
+        .and_then(|mut client| apply_settings(&mut client, &ctx.settings).map(|_| client));
+    start_line.wait();
+    let client = &mut connected?;
+    match method {
+        IngestMethod::BinaryCopy => binary_copy(client, &mut batches, ctx),
+        IngestMethod::InsertValues | IngestMethod::InsertValuesDoNothing | IngestMethod::InsertValuesDoUpdate => {
+            insert_values(client, method, &mut batches, ctx)
+        }
+        IngestMethod::PreparedInsertValues => prepared_insert_values(client, &mut batches, ctx),
+        IngestMethod::InsertUnnest
+        | IngestMethod::InsertUnnestDoNothing
+        | IngestMethod::InsertUnnestDoUpdate
+        | IngestMethod::Merge => insert_unnest(client, method, &mut batches, ctx),
+        IngestMethod::PreparedInsertUnnest => prepared_insert_unnest(client, &mut batches, ctx),
+        IngestMethod::Copy => copy(client, &mut batches, ctx),
+        IngestMethod::StagedCopy
+        | IngestMethod::StagedBinaryCopy
+        | IngestMethod::StagedCopyDoNothing
+        | IngestMethod::StagedCopyDoUpdate
+        | IngestMethod::StagedBinaryCopyDoNothing
+        | IngestMethod::StagedBinaryCopyDoUpdate => staged_copy(client, method, &mut batches, ctx),
+        IngestMethod::CopyFreeze | IngestMethod::BinaryCopyFreeze => copy_freeze(client, method, &mut batches, ctx),
+        IngestMethod::PipelinedInsertValues | IngestMethod::PipelinedInsertUnnest => unreachable!(),
+    }
 }
 
-fn insert_unnest(
-    client: &mut Client, 
-    records: &[BatterySensorData], 
-    transactions: bool, 
-    batch_size: usize, 
-    conn_info: &ConnectionInfo,
-    threads: usize
-) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>> {
-    truncate_table(client)?;
-    
+// Also runs the ON CONFLICT variants of UNNEST insert and MERGE
+fn insert_unnest(client: &mut Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+{
     let start = std::time::Instant::now();
-    if transactions {
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
         client.simple_query("BEGIN")?;
     }
 
-    let stmt = "INSERT INTO power_generation 
-                SELECT * FROM unnest($1::int4[], $2::timestamptz[], $3::float8[], $4::float8[], $5::float8[], $6::float8[], $7::float8[])";
+    let stmt = ctx.unnest_sql(method);
     
-    records.chunks(batch_size)
-        .try_for_each(|chunk| {
-            let mut timestamps: Vec<DateTime<Utc>> = Vec::with_capacity(chunk.len());
-            let mut ids: Vec<i32> = Vec::with_capacity(chunk.len());
-            let mut voltages: Vec<f64> = Vec::with_capacity(chunk.len());
-            let mut currents: Vec<f64> = Vec::with_capacity(chunk.len());
-            let mut temperatures: Vec<f64> = Vec::with_capacity(chunk.len());
-            let mut socs: Vec<f64> = Vec::with_capacity(chunk.len());
-            let mut resistances: Vec<f64> = Vec::with_capacity(chunk.len());
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        let columns = row::columns_of(&chunk, ctx.schema.columns.len());
+        client.execute(&stmt, &unnest_params(&columns))?;
+        latency.record(batch_start.elapsed());
+    }
 
-            for record in chunk {
-                ids.push(record.id);
-                timestamps.push(record.timestamp);
-                voltages.push(record.voltage);
-                currents.push(record.current);
-                temperatures.push(record.temperature);
-                socs.push(record.state_of_charge);
-                resistances.push(record.internal_resistance);
-            }
+    if ctx.transactions {
+        client.simple_query("COMMIT")?;
+    }
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, method.name(), duration, batches.rows(), latency))
+}
 
-            client.execute(stmt, &[&ids, &timestamps, &voltages, &currents, &temperatures, &socs, &resistances])?;
-            Ok::<(), Box<dyn Error + Send + Sync>>(())
-        })?;
+fn copy(client: &mut Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+{
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
+        client.simple_query("BEGIN")?;
+    }
+    
+    let copy_sql = ctx.schema.copy_sql();
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, None, &chunk)?;
+        latency.record(batch_start.elapsed());
+    }
 
-    if transactions {
+    if ctx.transactions {
         client.simple_query("COMMIT")?;
     }
     let duration = start.elapsed();
-    let rows_per_sec = records.len() as f64 / duration.as_secs_f64();
-    Ok(create_benchmark_result(
-        &conn_info.name, 
-        "UNNEST insert",
-        duration,
-        rows_per_sec,
-        transactions,
-        batch_size,
-        threads
-    ))
-}
-
-fn copy(client: &mut Client, records: &[BatterySensorData], transactions: bool, batch_size: usize, conn_info: &ConnectionInfo, threads: usize) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+    Ok(create_benchmark_result(ctx, "Copy", duration, batches.rows(), latency))
+}
+
+fn binary_copy(client: &mut Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
 {
-    truncate_table(client)?;
-    
     let start = std::time::Instant::now();
-    if transactions {
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
         client.simple_query("BEGIN")?;
     }
     
-    for chunk in records.chunks(batch_size) {
-        let mut writer = client.copy_in(
-            "COPY power_generation FROM STDIN"
-        )?;
+    let types = ctx.schema.types();
+    let copy_sql = ctx.schema.binary_copy_sql();
 
-        for record in chunk {
-            write!(writer, "{}\t", record.id)?;
-            write!(writer, "{}\t", record.timestamp)?;
-            write!(writer, "{}\t", record.voltage)?;
-            write!(writer, "{}\t", record.current)?;
-            write!(writer, "{}\t", record.temperature)?;
-            write!(writer, "{}\t", record.state_of_charge)?;
-            writeln!(writer, "{}", record.internal_resistance)?;
-        }
-        writer.finish()?;
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, Some(&types), &chunk)?;
+        latency.record(batch_start.elapsed());
     }
 
-    if transactions {
+    if ctx.transactions {
         client.simple_query("COMMIT")?;
     }
     let duration = start.elapsed();
-    let rows_per_sec = records.len() as f64 / duration.as_secs_f64();
-    Ok(create_benchmark_result(
-        &conn_info.name,
-        "Copy",
-        duration,
-        rows_per_sec,
-        transactions,
-        batch_size,
-        threads
-    ))
-}
-
-fn binary_copy(client: &mut Client, records: &[BatterySensorData], transactions: bool, batch_size: usize, conn_info: &ConnectionInfo, threads: usize) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+    Ok(create_benchmark_result(ctx, "Binary Copy", duration, batches.rows(), latency))
+}
+
+// COPY (or binary COPY) of each batch into a staging table of this connection, then
+// INSERT ... SELECT from there into the target table, with both phases timed
+fn staged_copy(client: &mut Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
 {
-    truncate_table(client)?;
-    
     let start = std::time::Instant::now();
-    if transactions {
+    let mut latency = LatencyHistogram::new();
+    let mut stages = StageTimes::default();
+    if ctx.transactions {
         client.simple_query("BEGIN")?;
     }
-    
-    let types = [
-        Type::INT4, Type::TIMESTAMPTZ, Type::FLOAT8, Type::FLOAT8,
-        Type::FLOAT8, Type::FLOAT8, Type::FLOAT8,
-    ];
 
+    // The backend pid keeps the unlogged staging tables of the workers apart
+    let pid: i32 = client.query_one("SELECT pg_backend_pid()", &[])?.get(0);
+    let name = format!("pgingester_staging_{}", pid);
+    let staging = TableSchema { table: name.clone(), name, columns: ctx.schema.columns.clone() };
+    client.batch_execute(&ctx.schema.create_staging_sql(&staging.table, ctx.staging_table.keyword()))?;
+    let move_sql = ctx.move_staged_sql(method, &staging.table);
+    let truncate_sql = format!("TRUNCATE {}", staging.table);
+    let binary = matches!(
+        method,
+        IngestMethod::StagedBinaryCopy | IngestMethod::StagedBinaryCopyDoNothing | IngestMethod::StagedBinaryCopyDoUpdate
+    );
+    let types = staging.types();
+    let copy_sql = if binary { staging.binary_copy_sql() } else { staging.copy_sql() };
 
-    for chunk in records.chunks(batch_size) {
-    	let writer = client.copy_in(
-       	 "COPY power_generation FROM STDIN WITH (FORMAT binary)"
-    	)?;
-    	let mut writer = BinaryCopyInWriter::new(writer, &types);
-        for record in chunk {
-           writer.write(&[&record.id, &record.timestamp, &record.voltage, &record.current, &record.temperature, &record.state_of_charge, &record.internal_resistance])?;
-        }
-        writer.finish()?;
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, binary.then_some(types.as_slice()), &chunk)?;
+        let copied = std::time::Instant::now();
+        client.execute(&move_sql, &[])?;
+        client.batch_execute(&truncate_sql)?;
+        stages.copy += copied - batch_start;
+        stages.insert += copied.elapsed();
+        latency.record(batch_start.elapsed());
     }
 
-    if transactions {
+    client.batch_execute(&format!("DROP TABLE {}", staging.table))?;
+    if ctx.transactions {
         client.simple_query("COMMIT")?;
     }
     let duration = start.elapsed();
-    let rows_per_sec = records.len() as f64 / duration.as_secs_f64();
-    Ok(create_benchmark_result(
-        &conn_info.name,
-        "Binary Copy",
-        duration,
-        rows_per_sec,
-        transactions,
-        batch_size,
-        threads
-    ))
-}
-
-fn insert_values(client: &mut Client, records: &[BatterySensorData], transactions: bool, batch_size: usize, conn_info: &ConnectionInfo, threads: usize) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+    let mut result = create_benchmark_result(ctx, &ctx.method_name(method), duration, batches.rows(), latency);
+    result.stages = Some(stages);
+    Ok(result)
+}
+
+// Truncates the table and loads it with COPY (or binary COPY) ... FREEZE in one
+// transaction, which lets the rows be written frozen and all-visible
+fn copy_freeze(client: &mut Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
 {
-    truncate_table(client)?;
-    
-    if batch_size > 4000 {
-        eprintln!("Insert Values with batch size of {} failed, too many parameters", batch_size);
-        return Ok(create_benchmark_result(
-            &conn_info.name,
-            "Insert VALUES",
-            std::time::Duration::from_secs(0),
-            0.0,
-            transactions,
-            batch_size,
-            threads
-        ));
+    let start = std::time::Instant::now();
+    let mut latency = LatencyHistogram::new();
+    client.simple_query("BEGIN")?;
+    client.simple_query(&format!("TRUNCATE TABLE {}", ctx.schema.table))?;
+
+    let types = ctx.schema.types();
+    let binary = method == IngestMethod::BinaryCopyFreeze;
+    let copy_sql = if binary { ctx.schema.binary_copy_freeze_sql() } else { ctx.schema.copy_freeze_sql() };
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        copy_chunk(client, &copy_sql, binary.then_some(types.as_slice()), &chunk)?;
+        latency.record(batch_start.elapsed());
     }
+
+    client.simple_query("COMMIT")?;
+    let duration = start.elapsed();
+    Ok(create_benchmark_result(ctx, method.name(), duration, batches.rows(), latency))
+}
+
+// One COPY of `chunk`, in binary format if the column `types` are given
+fn copy_chunk(client: &mut Client, copy_sql: &str, types: Option<&[Type]>, chunk: &[Row]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut writer = client.copy_in(copy_sql)?;
+    match types {
+        Some(types) => {
+            let mut writer = BinaryCopyInWriter::new(writer, types);
+            for record in chunk {
+                writer.write_raw(record.iter())?;
+            }
+            writer.finish()?;
+        }
+        None => {
+            let mut line = String::new();
+            for record in chunk {
+                line.clear();
+                row::write_copy_line(record, &mut line);
+                writer.write_all(line.as_bytes())?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
+
+// Also runs the ON CONFLICT variants of Insert VALUES
+fn insert_values(client: &mut Client, method: IngestMethod, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+{
     let start = std::time::Instant::now();
-    if transactions {
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
         client.simple_query("BEGIN")?;
     }
     
-    let mut value_strings = Vec::new();
-    for i in 0..batch_size {
-        let offset = i * 7;
-        value_strings.push(format!("(${}, ${}, ${}, ${}, ${}, ${}, ${})", 
-            offset + 1, offset + 2, offset + 3, offset + 4, offset + 5, offset + 6, offset + 7));
-    }
-    let query = format!(
-        "INSERT INTO power_generation 
-         VALUES {}", value_strings.join(", ")
-    );
+    let query = ctx.insert_values_sql(method, ctx.batch_size);
 
-    for chunk in records.chunks(batch_size) {
-        let mut params: Vec<&(dyn postgres::types::ToSql + Sync)> = Vec::with_capacity(chunk.len() * 7);
-        for record in chunk {
-            params.push(&record.id);
-            params.push(&record.timestamp);
-            params.push(&record.voltage);
-            params.push(&record.current);
-            params.push(&record.temperature);
-            params.push(&record.state_of_charge);
-            params.push(&record.internal_resistance);
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        if chunk.len() == ctx.batch_size {
+            client.execute(&query, &values_params(&chunk))?;
+        } else {
+            client.execute(&ctx.insert_values_sql(method, chunk.len()), &values_params(&chunk))?;
         }
-        client.execute(&query, &params[..])?;
+        latency.record(batch_start.elapsed());
     }
 
-    if transactions {
+    if ctx.transactions {
         client.simple_query("COMMIT")?;
     }
     let duration = start.elapsed();
-    let rows_per_sec = records.len() as f64 / duration.as_secs_f64();
-    Ok(create_benchmark_result(
-        &conn_info.name,
-        "Insert VALUES",
-        duration,
-        rows_per_sec,
-        transactions,
-        batch_size,
-        threads
-    ))
-}
-
-fn prepared_insert_values(client: &mut Client, records: &[BatterySensorData], transactions: bool, batch_size: usize, conn_info: &ConnectionInfo, threads: usize) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+    Ok(create_benchmark_result(ctx, method.name(), duration, batches.rows(), latency))
+}
+
+fn prepared_insert_values(client: &mut Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
 {
-    truncate_table(client)?;
-    
-    if batch_size > 4000 {
-        eprintln!("Prepared Insert Values with batch size of {} failed, too many parameters", batch_size);
-        return Ok(create_benchmark_result(
-            &conn_info.name,
-            "Prepared Insert VALUES",
-            std::time::Duration::from_secs(0),
-            0.0,
-            transactions,
-            batch_size,
-            threads
-        ));
-    }
     let start = std::time::Instant::now();
-    if transactions {
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
         client.simple_query("BEGIN")?;
     }
     
-    let mut value_strings = Vec::new();
-    for i in 0..batch_size {
-        let offset = i * 7;
-        value_strings.push(format!("(${}, ${}, ${}, ${}, ${}, ${}, ${})", 
-            offset + 1, offset + 2, offset + 3, offset + 4, offset + 5, offset + 6, offset + 7));
-    }
-    let query = format!(
-        "INSERT INTO power_generation 
-         VALUES {}", value_strings.join(", ")
-    );
-
-    let stmt = client.prepare(&query)?;
+    let stmt = client.prepare(&ctx.schema.insert_values_sql(ctx.batch_size))?;
 
-    for chunk in records.chunks(batch_size) {
-        let mut params: Vec<&(dyn postgres::types::ToSql + Sync)> = Vec::with_capacity(chunk.len() * 7);
-        for record in chunk {
-            params.push(&record.timestamp);
-            params.push(&record.id);
-            params.push(&record.voltage);
-            params.push(&record.current);
-            params.push(&record.temperature);
-            params.push(&record.state_of_charge);
-            params.push(&record.internal_resistance);
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        if chunk.len() == ctx.batch_size {
+            client.execute(&stmt, &values_params(&chunk))?;
+        } else {
+            let remainder_stmt = client.prepare(&ctx.schema.insert_values_sql(chunk.len()))?;
+            client.execute(&remainder_stmt, &values_params(&chunk))?;
         }
-        client.execute(&stmt, &params[..])?;
+        latency.record(batch_start.elapsed());
     }
 
-    if transactions {
+    if ctx.transactions {
         client.simple_query("COMMIT")?;
     }
     let duration = start.elapsed();
-    let rows_per_sec = records.len() as f64 / duration.as_secs_f64();
-    Ok(create_benchmark_result(
-        &conn_info.name,
-        "Prepared Insert VALUES",
-        duration,
-        rows_per_sec,
-        transactions,
-        batch_size,
-        threads
-    ))
-}
-
-fn prepared_insert_unnest(client: &mut Client, records: &[BatterySensorData], transactions: bool, batch_size: usize, conn_info: &ConnectionInfo, threads: usize) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
+    Ok(create_benchmark_result(ctx, "Prepared Insert VALUES", duration, batches.rows(), latency))
+}
+
+fn prepared_insert_unnest(client: &mut Client, batches: &mut Batches<'_>, ctx: &IngestContext) -> Result<BenchmarkResult, Box<dyn Error + Send + Sync>>
 {
-    truncate_table(client)?;
-    
     let start = std::time::Instant::now();
-    if transactions {
+    let mut latency = LatencyHistogram::new();
+    if ctx.transactions {
         client.simple_query("BEGIN")?;
     }
-    let stmt = client.prepare(
-        "INSERT INTO power_generation 
-         SELECT * FROM unnest($1::int4[], $2::timestamptz[], $3::float8[], $4::float8[], $5::float8[], $6::float8[], $7::float8[])"
-    )?;
-
+    let stmt = client.prepare(&ctx.schema.unnest_sql())?;
 
-    for chunk in records.chunks(batch_size) {
-        let mut timestamps: Vec<DateTime<Utc>> = Vec::with_capacity(chunk.len());
-        let mut ids: Vec<i32> = Vec::with_capacity(chunk.len());
-        let mut voltages: Vec<f64> = Vec::with_capacity(chunk.len());
-        let mut currents: Vec<f64> = Vec::with_capacity(chunk.len());
-        let mut temperatures: Vec<f64> = Vec::with_capacity(chunk.len());
-        let mut socs: Vec<f64> = Vec::with_capacity(chunk.len());
-        let mut resistances: Vec<f64> = Vec::with_capacity(chunk.len());
-
-        for record in chunk {
-            ids.push(record.id);
-            timestamps.push(record.timestamp);
-            voltages.push(record.voltage);
-            currents.push(record.current);
-            temperatures.push(record.temperature);
-            socs.push(record.state_of_charge);
-            resistances.push(record.internal_resistance);
-        }
-
-        client.execute(&stmt, &[&ids, &timestamps, &voltages, &currents, &temperatures, &socs, &resistances])?;
+    while let Some(chunk) = batches.next_blocking() {
+        let batch_start = std::time::Instant::now();
+        let columns = row::columns_of(&chunk, ctx.schema.columns.len());
+        client.execute(&stmt, &unnest_params(&columns))?;
+        latency.record(batch_start.elapsed());
     }
 
-    if transactions {
+    if ctx.transactions {
         client.simple_query("COMMIT")?;
     }
     let duration = start.elapsed();
-    let rows_per_sec = records.len() as f64 / duration.as_secs_f64();
-    Ok(create_benchmark_result(
-        &conn_info.name,
-        "Prepared Insert UNNEST",
-        duration,
-        rows_per_sec,
-        transactions,
-        batch_size,
-        threads
-    ))
+    Ok(create_benchmark_result(ctx, "Prepared Insert UNNEST", duration, batches.rows(), latency))
+}
+
+// Sets the session settings of the benchmark on a worker connection, like
+// async_engine::apply_settings
+fn apply_settings(client: &mut Client, settings: &[(String, String)]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some((query, params)) = scenario::set_config_query(settings) {
+        client.execute(&query, &params).map_err(|e| scenario::settings_error(settings, e))?;
+    }
+    Ok(())
+}
+
+// Connects a tokio-postgres client driven by a single-threaded runtime, so the
+// blocking workers can use the pipelining support of the async client.
+fn pipelined_client(
+    connector: &Connector,
+    ctx: &IngestContext,
+) -> Result<(tokio::runtime::Runtime, tokio_postgres::Client), Box<dyn Error + Send + Sync>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let client = runtime.block_on(async {
+        let client = async_engine::connect(connector, &ctx.conn_info).await?;
+        async_engine::apply_settings(&client, &ctx.settings).await?;
+        Ok::<_, Box<dyn Error + Send + Sync>>(client)
+    })?;
+    Ok((runtime, client))
 }
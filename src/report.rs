@@ -0,0 +1,345 @@
+//! Machine-readable results for `--output json` and `--output jsonl`, together with
+//! the metadata needed to tell archived runs apart, and their storage in a results
+//! database with `--results-db`.
+
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use postgres::Client;
+use serde_json::{json, Map, Value};
+
+use crate::tls::Connector;
+use crate::{BenchmarkResult, LATENCY_PERCENTILES};
+
+// Server settings that most affect ingest speed
+const REPORTED_SETTINGS: &[&str] = &[
+    "shared_buffers",
+    "wal_buffers",
+    "wal_level",
+    "wal_compression",
+    "full_page_writes",
+    "fsync",
+    "synchronous_commit",
+    "commit_delay",
+    "max_wal_size",
+    "checkpoint_timeout",
+    "max_connections",
+    "work_mem",
+    "maintenance_work_mem",
+];
+
+/// Version and settings of one target server
+pub struct ServerInfo {
+    pub connection: String,
+    pub version: String,
+    pub settings: Vec<(String, String)>,
+}
+
+impl ServerInfo {
+    pub fn fetch(client: &mut Client, connection: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let version: String = client.query_one("SELECT version()", &[])?.get(0);
+        let settings = client
+            .query(
+                "SELECT name, current_setting(name) FROM pg_settings WHERE name = ANY($1) ORDER BY name",
+                &[&REPORTED_SETTINGS],
+            )?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        Ok(ServerInfo { connection: connection.to_string(), version, settings })
+    }
+}
+
+/// Everything about a run that isn't a result
+pub struct RunMetadata {
+    pub started_at: DateTime<Utc>,
+    pub args: Vec<String>,
+    /// Input file path, or `synthetic` for generated rows
+    pub input: String,
+    pub rows: usize,
+    pub rejected_rows: usize,
+    pub servers: Vec<ServerInfo>,
+    /// The --scenario file, if any
+    pub scenario: Option<Value>,
+}
+
+impl RunMetadata {
+    fn to_json(&self) -> Value {
+        let servers: Vec<Value> = self
+            .servers
+            .iter()
+            .map(|server| {
+                let settings: Map<String, Value> = server
+                    .settings
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Value::from(value.as_str())))
+                    .collect();
+                json!({
+                    "connection": server.connection,
+                    "version": server.version,
+                    "settings": settings,
+                })
+            })
+            .collect();
+        json!({
+            "started_at": self.started_at.to_rfc3339(),
+            "args": self.args.iter().map(|arg| redact_passwords(arg)).collect::<Vec<_>>(),
+            "input": self.input,
+            "rows": self.rows,
+            "rejected_rows": self.rejected_rows,
+            "servers": servers,
+            "scenario": self.scenario,
+            "hostname": client_hostname(),
+            "cpus": std::thread::available_parallelism().map_or(0, |n| n.get()),
+            "version": env!("CARGO_PKG_VERSION"),
+        })
+    }
+}
+
+fn client_hostname() -> Option<String> {
+    hostname::get().ok().map(|name| name.to_string_lossy().into_owned())
+}
+
+// Masks the password of every URL-style connection string in a (comma-separated) argument,
+// and password= values of key/value connection strings
+pub fn redact_passwords(arg: &str) -> String {
+    redact_key_value_passwords(arg)
+        .split(',')
+        .map(|part| {
+            let Some((scheme, rest)) = part.split_once("://") else {
+                return part.to_string();
+            };
+            match rest.split_once('@') {
+                Some((userinfo, host)) if userinfo.contains(':') => {
+                    let user = userinfo.split(':').next().unwrap_or_default();
+                    format!("{}://{}:***@{}", scheme, user, host)
+                }
+                _ => part.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn redact_key_value_passwords(arg: &str) -> String {
+    let mut redacted = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find("password") {
+        let (before, after) = rest.split_at(start + "password".len());
+        redacted.push_str(before);
+        let value = after.trim_start();
+        let Some(value) = value.strip_prefix('=').map(str::trim_start) else {
+            rest = after;
+            continue;
+        };
+        // Skip a quoted value with \ escapes, or an unquoted one up to whitespace
+        let mut chars = value.char_indices();
+        let end = match chars.next() {
+            Some((_, '\'')) => {
+                let mut escaped = false;
+                chars
+                    .find(|&(_, c)| {
+                        let closing = c == '\'' && !escaped;
+                        escaped = c == '\\' && !escaped;
+                        closing
+                    })
+                    .map_or(value.len(), |(i, _)| i + 1)
+            }
+            _ => value.find(char::is_whitespace).unwrap_or(value.len()),
+        };
+        redacted.push_str("=***");
+        rest = &value[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn result_json(result: &BenchmarkResult) -> Value {
+    let mut latency: Map<String, Value> = LATENCY_PERCENTILES
+        .iter()
+        .map(|(name, percentile)| (name.replace('.', "_"), Value::from(millis(result.latency.percentile(*percentile)))))
+        .collect();
+    latency.insert("max".to_string(), Value::from(millis(result.latency.max())));
+    json!({
+        "connection": result.connection_name,
+        "table": result.table,
+        "method": result.method,
+        "batch_size": result.batch_size,
+        "transaction": result.transaction,
+        "settings": result.settings.iter().map(|(name, value)| (name.clone(), Value::from(value.as_str()))).collect::<Map<_, _>>(),
+        "tls": result.tls,
+        "threads": result.threads,
+        "iterations": result.iterations,
+        "rows": result.rows,
+        "setup_ms": millis(result.setup),
+        "duration_ms": millis(result.duration),
+        "rows_per_sec": result.rows_per_sec,
+        "tls_cost_pct": result.tls_cost,
+        "stage_copy_ms": result.stages.map(|stages| millis(stages.copy)),
+        "stage_insert_ms": result.stages.map(|stages| millis(stages.insert)),
+        "frozen_pages": result.frozen_pages.map(|frozen| frozen.all_frozen),
+        "table_pages": result.frozen_pages.map(|frozen| frozen.pages),
+        "rows_per_sec_stats": {
+            "min": result.throughput.min,
+            "max": result.throughput.max,
+            "mean": result.throughput.mean,
+            "median": result.throughput.median,
+            "p95": result.throughput.p95,
+            "stddev": result.throughput.stddev,
+        },
+        "batch_latency_ms": latency,
+        "server": {
+            "wal_bytes": result.server.wal_bytes,
+            "wal_records": result.server.wal_records,
+            "wal_fpi": result.server.wal_fpi,
+            "buffers_written": result.server.buffers_written,
+            "table_size": result.server.table_size,
+        },
+    })
+}
+
+/// One JSON document holding the metadata and every result
+pub fn print_json(results: &[BenchmarkResult], metadata: &RunMetadata) {
+    let document = json!({
+        "run": metadata.to_json(),
+        "results": results.iter().map(result_json).collect::<Vec<_>>(),
+    });
+    println!("{}", serde_json::to_string_pretty(&document).expect("JSON values always serialize"));
+}
+
+/// One line per result, each carrying the run metadata so lines can be loaded on their own
+pub fn print_jsonl(results: &[BenchmarkResult], metadata: &RunMetadata) {
+    let run = metadata.to_json();
+    for result in results {
+        let mut line = result_json(result);
+        line["run"] = run.clone();
+        println!("{}", line);
+    }
+}
+
+const RESULTS_DDL: &str = "
+    CREATE TABLE IF NOT EXISTS pgingester_runs (
+        run_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+        started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+        finished_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+        hostname TEXT,
+        input TEXT,
+        rows BIGINT,
+        metadata JSONB NOT NULL              -- The run object of --output json
+    );
+    CREATE TABLE IF NOT EXISTS pgingester_results (
+        run_id BIGINT NOT NULL REFERENCES pgingester_runs ON DELETE CASCADE,
+        connection TEXT NOT NULL,
+        method TEXT NOT NULL,
+        batch_size INTEGER NOT NULL,
+        transaction BOOLEAN NOT NULL,
+        threads INTEGER NOT NULL,
+        iterations INTEGER NOT NULL,
+        rows BIGINT NOT NULL,
+        setup_ms DOUBLE PRECISION,
+        duration_ms DOUBLE PRECISION,
+        rows_per_sec DOUBLE PRECISION,       -- Mean over iterations
+        rows_per_sec_min DOUBLE PRECISION,
+        rows_per_sec_median DOUBLE PRECISION,
+        rows_per_sec_p95 DOUBLE PRECISION,
+        rows_per_sec_max DOUBLE PRECISION,
+        rows_per_sec_stddev DOUBLE PRECISION,
+        batch_latency_ms JSONB,              -- Percentiles as in --output json
+        wal_bytes BIGINT,
+        wal_records BIGINT,
+        wal_fpi BIGINT,
+        buffers_written BIGINT,
+        table_size BIGINT,
+        target_table TEXT,
+        tls BOOLEAN,
+        tls_cost_pct DOUBLE PRECISION,
+        settings JSONB,                      -- Session settings as in --output json
+        stage_copy_ms DOUBLE PRECISION,
+        stage_insert_ms DOUBLE PRECISION,
+        frozen_pages BIGINT,                 -- All-frozen pages after a COPY FREEZE run
+        table_pages BIGINT
+    );
+";
+
+/// Stores the run and its results in the results database at `connection_string`,
+/// creating the tables on first use. Returns the new run id.
+pub fn save(
+    connection_string: &str,
+    connector: &Connector,
+    results: &[BenchmarkResult],
+    metadata: &RunMetadata,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    let mut client = connector.connect(connection_string)?;
+    client.batch_execute(RESULTS_DDL)?;
+
+    let mut transaction = client.transaction()?;
+    let run_id: i64 = transaction
+        .query_one(
+            "INSERT INTO pgingester_runs (started_at, hostname, input, rows, metadata)
+             VALUES ($1, $2, $3, $4, $5) RETURNING run_id",
+            &[
+                &metadata.started_at,
+                &client_hostname(),
+                &metadata.input,
+                &(metadata.rows as i64),
+                &metadata.to_json(),
+            ],
+        )?
+        .get(0);
+
+    let insert = transaction.prepare(
+        "INSERT INTO pgingester_results (
+             run_id, connection, method, batch_size, transaction, threads, iterations, rows,
+             setup_ms, duration_ms, rows_per_sec, rows_per_sec_min, rows_per_sec_median,
+             rows_per_sec_p95, rows_per_sec_max, rows_per_sec_stddev, batch_latency_ms,
+             wal_bytes, wal_records, wal_fpi, buffers_written, table_size, target_table, tls, tls_cost_pct, settings,
+             stage_copy_ms, stage_insert_ms, frozen_pages, table_pages)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23,
+                 $24, $25, $26, $27, $28, $29, $30)",
+    )?;
+    for result in results {
+        let json = result_json(result);
+        transaction.execute(
+            &insert,
+            &[
+                &run_id,
+                &result.connection_name,
+                &result.method,
+                &(result.batch_size as i32),
+                &result.transaction,
+                &(result.threads as i32),
+                &(result.iterations as i32),
+                &(result.rows as i64),
+                &millis(result.setup),
+                &millis(result.duration),
+                &result.rows_per_sec,
+                &result.throughput.min,
+                &result.throughput.median,
+                &result.throughput.p95,
+                &result.throughput.max,
+                &result.throughput.stddev,
+                &json["batch_latency_ms"],
+                &result.server.wal_bytes,
+                &result.server.wal_records,
+                &result.server.wal_fpi,
+                &result.server.buffers_written,
+                &result.server.table_size,
+                &result.table,
+                &result.tls,
+                &result.tls_cost,
+                &json["settings"],
+                &result.stages.map(|stages| millis(stages.copy)),
+                &result.stages.map(|stages| millis(stages.insert)),
+                &result.frozen_pages.map(|frozen| frozen.all_frozen),
+                &result.frozen_pages.map(|frozen| frozen.pages),
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(run_id)
+}
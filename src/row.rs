@@ -0,0 +1,474 @@
+//! Dynamically typed row model. A [`Row`] holds one [`Value`] per target column, in
+//! schema order, so the ingest methods and the COPY writers work over any column list.
+
+use std::error::Error;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use bytes::BytesMut;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use postgres::types::{to_sql_checked, IsNull, Kind, ToSql, Type};
+use postgres_protocol::types::{self as protocol, ArrayDimension};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::schema::TableSchema;
+
+pub type Row = Box<[Value]>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Float4(f32),
+    Float8(f64),
+    Numeric(Decimal),
+    Text(Box<str>),
+    Timestamp(NaiveDateTime),
+    TimestampTz(DateTime<Utc>),
+    Date(NaiveDate),
+    Json(Box<serde_json::Value>),
+    Uuid(Uuid),
+    Bytea(Box<[u8]>),
+    Array(Box<[Value]>),
+}
+
+/// Whether [`Value::parse`] and the binary encoders can handle columns of type `ty`
+pub fn is_supported(ty: &Type) -> bool {
+    match ty.kind() {
+        Kind::Array(member) => !matches!(member.kind(), Kind::Array(_)) && is_supported(member),
+        _ => matches!(
+            *ty,
+            Type::BOOL
+                | Type::INT2
+                | Type::INT4
+                | Type::INT8
+                | Type::FLOAT4
+                | Type::FLOAT8
+                | Type::NUMERIC
+                | Type::TEXT
+                | Type::VARCHAR
+                | Type::BPCHAR
+                | Type::NAME
+                | Type::TIMESTAMP
+                | Type::TIMESTAMPTZ
+                | Type::DATE
+                | Type::JSON
+                | Type::JSONB
+                | Type::UUID
+                | Type::BYTEA
+        ),
+    }
+}
+
+/// Fails with the list of columns whose type has no [`Value`] representation
+pub fn check_supported(schema: &TableSchema) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let unsupported: Vec<String> = schema
+        .columns
+        .iter()
+        .filter(|c| !is_supported(&c.data_type))
+        .map(|c| format!("{} ({})", c.name, c.data_type.name()))
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(format!("Table {} has columns of unsupported types: {}", schema.table, unsupported.join(", ")).into());
+    }
+    Ok(())
+}
+
+impl Value {
+    /// Parses the text representation of a value for a column of type `ty`.
+    /// Empty input is NULL, as for unquoted empty fields in `COPY ... (FORMAT csv)`.
+    pub fn parse(input: &str, ty: &Type) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        if input.is_empty() {
+            return Ok(Value::Null);
+        }
+        Self::parse_non_null(input, ty)
+            .map_err(|e| format!("invalid {} value '{}': {}", ty.name(), input, e).into())
+    }
+
+    fn parse_non_null(input: &str, ty: &Type) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        if let Kind::Array(member) = ty.kind() {
+            return Ok(Value::Array(parse_array(input, member)?));
+        }
+        let value = match *ty {
+            Type::BOOL => Value::Bool(parse_bool(input)?),
+            Type::INT2 => Value::Int2(input.trim().parse()?),
+            Type::INT4 => Value::Int4(input.trim().parse()?),
+            Type::INT8 => Value::Int8(input.trim().parse()?),
+            Type::FLOAT4 => Value::Float4(input.trim().parse()?),
+            Type::FLOAT8 => Value::Float8(input.trim().parse()?),
+            Type::NUMERIC => Value::Numeric(
+                Decimal::from_str(input.trim()).or_else(|_| Decimal::from_scientific(input.trim()))?,
+            ),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => Value::Text(input.into()),
+            Type::TIMESTAMPTZ => Value::TimestampTz(parse_timestamptz(input.trim())?),
+            Type::TIMESTAMP => Value::Timestamp(parse_timestamp(input.trim())?),
+            Type::DATE => Value::Date(NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d")?),
+            Type::JSON | Type::JSONB => Value::Json(Box::new(serde_json::from_str(input)?)),
+            Type::UUID => Value::Uuid(Uuid::parse_str(input.trim())?),
+            Type::BYTEA => Value::Bytea(parse_bytea(input)?),
+            _ => return Err(format!("type {} is not supported", ty.name()).into()),
+        };
+        Ok(value)
+    }
+
+    /// Appends the value in `COPY ... FROM STDIN` text format
+    pub fn write_copy_text(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("\\N"),
+            Value::Text(v) => escape_copy(v, out),
+            Value::Json(v) => escape_copy(&v.to_string(), out),
+            Value::Bytea(_) | Value::Array(_) => {
+                let mut literal = String::new();
+                self.write_literal(&mut literal);
+                escape_copy(&literal, out);
+            }
+            _ => self.write_literal(out),
+        }
+    }
+
+    // PostgreSQL text input representation of the value
+    fn write_literal(&self, out: &mut String) {
+        // Writing to a String cannot fail
+        let _ = match self {
+            Value::Null => write!(out, "NULL"),
+            Value::Bool(v) => write!(out, "{}", if *v { 't' } else { 'f' }),
+            Value::Int2(v) => write!(out, "{}", v),
+            Value::Int4(v) => write!(out, "{}", v),
+            Value::Int8(v) => write!(out, "{}", v),
+            Value::Float4(v) => write!(out, "{}", v),
+            Value::Float8(v) => write!(out, "{}", v),
+            Value::Numeric(v) => write!(out, "{}", v),
+            Value::Text(v) => write!(out, "{}", v),
+            Value::Timestamp(v) => write!(out, "{}", v.format("%Y-%m-%d %H:%M:%S%.f")),
+            Value::TimestampTz(v) => write!(out, "{}", v.format("%Y-%m-%d %H:%M:%S%.f+00")),
+            Value::Date(v) => write!(out, "{}", v.format("%Y-%m-%d")),
+            Value::Json(v) => write!(out, "{}", v),
+            Value::Uuid(v) => write!(out, "{}", v),
+            Value::Bytea(v) => {
+                out.push_str("\\x");
+                v.iter().try_for_each(|b| write!(out, "{:02x}", b))
+            }
+            Value::Array(elements) => {
+                out.push('{');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    if *element == Value::Null {
+                        out.push_str("NULL");
+                        continue;
+                    }
+                    let mut literal = String::new();
+                    element.write_literal(&mut literal);
+                    out.push('"');
+                    for c in literal.chars() {
+                        if c == '"' || c == '\\' {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                    out.push('"');
+                }
+                out.push('}');
+                Ok(())
+            }
+        };
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match self {
+            Value::Null => Ok(IsNull::Yes),
+            Value::Bool(v) => v.to_sql_checked(ty, out),
+            Value::Int2(v) => v.to_sql_checked(ty, out),
+            Value::Int4(v) => v.to_sql_checked(ty, out),
+            Value::Int8(v) => v.to_sql_checked(ty, out),
+            Value::Float4(v) => v.to_sql_checked(ty, out),
+            Value::Float8(v) => v.to_sql_checked(ty, out),
+            Value::Numeric(v) => v.to_sql_checked(ty, out),
+            Value::Text(v) => (&**v).to_sql_checked(ty, out),
+            Value::Timestamp(v) => v.to_sql_checked(ty, out),
+            Value::TimestampTz(v) => v.to_sql_checked(ty, out),
+            Value::Date(v) => v.to_sql_checked(ty, out),
+            Value::Json(v) => (**v).to_sql_checked(ty, out),
+            Value::Uuid(v) => v.to_sql_checked(ty, out),
+            Value::Bytea(v) => (&**v).to_sql_checked(ty, out),
+            Value::Array(elements) => encode_array(elements.iter(), ty, out),
+        }
+    }
+
+    // Each variant checks the column type itself in to_sql
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+/// Appends `row` as one tab-separated line of `COPY ... FROM STDIN` text format
+pub fn write_copy_line(row: &[Value], out: &mut String) {
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            out.push('\t');
+        }
+        value.write_copy_text(out);
+    }
+    out.push('\n');
+}
+
+/// One column of a batch, bound as a single array parameter by the UNNEST methods
+#[derive(Debug)]
+pub struct ColumnArray<'a>(pub Vec<&'a Value>);
+
+impl ToSql for ColumnArray<'_> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        encode_array(self.0.iter().copied(), ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Array(_))
+    }
+
+    to_sql_checked!();
+}
+
+/// Splits a batch of rows into one [`ColumnArray`] per column
+pub fn columns_of(chunk: &[Row], width: usize) -> Vec<ColumnArray<'_>> {
+    let mut columns: Vec<ColumnArray> = (0..width).map(|_| ColumnArray(Vec::with_capacity(chunk.len()))).collect();
+    for row in chunk {
+        for (column, value) in columns.iter_mut().zip(row.iter()) {
+            column.0.push(value);
+        }
+    }
+    columns
+}
+
+fn encode_array<'a, I>(elements: I, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>>
+where
+    I: ExactSizeIterator<Item = &'a Value>,
+{
+    let member = match ty.kind() {
+        Kind::Array(member) => member,
+        _ => return Err(format!("cannot bind an array to a {} column", ty.name()).into()),
+    };
+    let dimension = ArrayDimension {
+        len: i32::try_from(elements.len())?,
+        lower_bound: 1,
+    };
+    protocol::array_to_sql(
+        Some(dimension),
+        member.oid(),
+        elements,
+        |element, buf| match element.to_sql(member, buf)? {
+            IsNull::No => Ok(postgres_protocol::IsNull::No),
+            IsNull::Yes => Ok(postgres_protocol::IsNull::Yes),
+        },
+        out,
+    )?;
+    Ok(IsNull::No)
+}
+
+fn escape_copy(input: &str, out: &mut String) {
+    for c in input.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn parse_bool(input: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "t" | "true" | "y" | "yes" | "on" | "1" => Ok(true),
+        "f" | "false" | "n" | "no" | "off" | "0" => Ok(false),
+        _ => Err("expected true or false".into()),
+    }
+}
+
+fn parse_timestamptz(input: &str) -> Result<DateTime<Utc>, Box<dyn Error + Send + Sync>> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(input) {
+        return Ok(ts.into());
+    }
+    if let Ok(ts) = DateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S%.f%#z") {
+        return Ok(ts.into());
+    }
+    // Without an offset the timestamp is taken as UTC
+    Ok(parse_timestamp(input)?.and_utc())
+}
+
+fn parse_timestamp(input: &str) -> Result<NaiveDateTime, Box<dyn Error + Send + Sync>> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(input) {
+        return Ok(ts.naive_utc());
+    }
+    NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M:%S%.f"))
+        .map_err(Into::into)
+}
+
+fn parse_bytea(input: &str) -> Result<Box<[u8]>, Box<dyn Error + Send + Sync>> {
+    match input.strip_prefix("\\x") {
+        Some(hex) if hex.len() % 2 == 0 => (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(Into::into))
+            .collect(),
+        Some(_) => Err("odd number of hex digits".into()),
+        None => parse_bytea_escape(input),
+    }
+}
+
+// The escape format: \\ is a backslash, \ooo an octal byte, anything else stands for itself
+fn parse_bytea_escape(input: &str) -> Result<Box<[u8]>, Box<dyn Error + Send + Sync>> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            decoded.push(bytes[i]);
+            i += 1;
+        } else if bytes.get(i + 1) == Some(&b'\\') {
+            decoded.push(b'\\');
+            i += 2;
+        } else {
+            let octal = bytes
+                .get(i + 1..i + 4)
+                .filter(|digits| digits[0] <= b'3' && digits.iter().all(|d| (b'0'..=b'7').contains(d)))
+                .ok_or("invalid escape, expected \\\\ or \\ followed by three octal digits")?;
+            decoded.push(octal.iter().fold(0, |byte, digit| byte * 8 + (digit - b'0')));
+            i += 4;
+        }
+    }
+    Ok(decoded.into())
+}
+
+// Parses a one-dimensional array literal such as {1,2,NULL} or {"a b","c\"d"}
+fn parse_array(input: &str, member: &Type) -> Result<Box<[Value]>, Box<dyn Error + Send + Sync>> {
+    let inner = input
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or("array literals must be enclosed in braces")?;
+
+    let mut elements = Vec::new();
+    if inner.trim().is_empty() {
+        return Ok(elements.into());
+    }
+
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut element = String::new();
+        let quoted = chars.next_if_eq(&'"').is_some();
+        if quoted {
+            loop {
+                match chars.next() {
+                    Some('\\') => element.push(chars.next().ok_or("unterminated escape")?),
+                    Some('"') => break,
+                    Some(c) => element.push(c),
+                    None => return Err("unterminated quoted element".into()),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                element.push(c);
+            }
+            element.truncate(element.trim_end().len());
+        }
+
+        if !quoted && element.eq_ignore_ascii_case("NULL") {
+            elements.push(Value::Null);
+        } else if element.is_empty() && !quoted {
+            return Err("empty array element".into());
+        } else {
+            elements.push(Value::parse_non_null(&element, member)?);
+        }
+
+        match chars.next() {
+            Some(',') => continue,
+            None => break,
+            Some(c) => return Err(format!("unexpected '{}' in array literal", c).into()),
+        }
+    }
+    Ok(elements.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.into())
+    }
+
+    fn copy_text(value: &Value) -> String {
+        let mut out = String::new();
+        value.write_copy_text(&mut out);
+        out
+    }
+
+    #[test]
+    fn parse_array_of_integers() {
+        let parsed = parse_array("{1, 2 ,NULL,3}", &Type::INT4).unwrap();
+        assert_eq!(&*parsed, &[Value::Int4(1), Value::Int4(2), Value::Null, Value::Int4(3)]);
+        assert!(parse_array("{}", &Type::INT4).unwrap().is_empty());
+        assert!(parse_array(" { } ", &Type::INT4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_array_quoted_elements() {
+        let parsed = parse_array(r#"{"a b","c\"d","e\\f","NULL",plain, "x,y" }"#, &Type::TEXT).unwrap();
+        assert_eq!(&*parsed, &[text("a b"), text("c\"d"), text("e\\f"), text("NULL"), text("plain"), text("x,y")]);
+        assert_eq!(&*parse_array(r#"{""}"#, &Type::TEXT).unwrap(), &[text("")]);
+    }
+
+    #[test]
+    fn parse_array_rejects_malformed_literals() {
+        assert!(parse_array("1,2", &Type::INT4).is_err());
+        assert!(parse_array("{1,,2}", &Type::INT4).is_err());
+        assert!(parse_array(r#"{"open}"#, &Type::TEXT).is_err());
+        assert!(parse_array(r#"{"a"b}"#, &Type::TEXT).is_err());
+        assert!(parse_array("{1,x}", &Type::INT4).is_err());
+    }
+
+    #[test]
+    fn parse_bytea_hex_and_escape_formats() {
+        assert_eq!(&*parse_bytea("\\x00ff10").unwrap(), &[0x00, 0xff, 0x10]);
+        assert_eq!(&*parse_bytea("\\x").unwrap(), &[] as &[u8]);
+        assert!(parse_bytea("\\x0").is_err());
+        assert!(parse_bytea("\\xzz").is_err());
+
+        assert_eq!(&*parse_bytea("abc").unwrap(), b"abc");
+        assert_eq!(&*parse_bytea(r"a\000b\\c\377\047").unwrap(), b"a\0b\\c\xff'");
+        assert!(parse_bytea(r"\").is_err());
+        assert!(parse_bytea(r"\12").is_err());
+        assert!(parse_bytea(r"\400").is_err());
+        assert!(parse_bytea(r"\08a").is_err());
+    }
+
+    #[test]
+    fn write_copy_text_escapes_specials() {
+        assert_eq!(copy_text(&Value::Null), "\\N");
+        assert_eq!(copy_text(&text("a\tb\nc\rd\\e")), "a\\tb\\nc\\rd\\\\e");
+        assert_eq!(copy_text(&text("\\N")), "\\\\N");
+        assert_eq!(copy_text(&Value::Json(Box::new(serde_json::json!({"k": "a\\b"})))), r#"{"k":"a\\\\b"}"#);
+        assert_eq!(copy_text(&Value::Bytea(vec![0xde, 0xad].into())), "\\\\xdead");
+    }
+
+    #[test]
+    fn write_copy_text_arrays() {
+        let array = Value::Array(vec![text("a\"b"), Value::Null, text("c\\d"), text("e\tf")].into());
+        // Array quoting first, then COPY escaping of the whole literal
+        assert_eq!(copy_text(&array), r#"{"a\\"b",NULL,"c\\\\d","e\tf"}"#);
+        let mut line = String::new();
+        write_copy_line(&[Value::Int4(1), Value::Null, Value::Bool(true)], &mut line);
+        assert_eq!(line, "1\t\\N\tt\n");
+    }
+}
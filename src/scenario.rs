@@ -0,0 +1,224 @@
+//! The benchmark matrix: every combination of connection, table, thread count,
+//! transaction mode, batch size and method that one invocation measures.
+//!
+//! The command line describes a matrix with a single table; `--scenario bench.toml`
+//! describes all of it in one file:
+//!
+//! ```toml
+//! connections = ["local=postgresql://postgres@localhost/postgres"]
+//! methods = ["binary-copy", "insert-unnest"]
+//! batch_sizes = [1000, 10000]
+//! threads = [1, 4, 16]
+//! transactions = [false, true]
+//! iterations = 3
+//! warmup = 1
+//!
+//! [settings]
+//! synchronous_commit = ["on", "off"]
+//! wal_compression = "lz4"
+//!
+//! [[tables]]
+//! name = "power_generation"
+//!
+//! [[tables]]
+//! name = "power_generation_unlogged"
+//! ddl = "unlogged.sql"
+//! ```
+//!
+//! Keys missing from the file keep their command-line values.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use postgres::types::ToSql;
+use serde::Deserialize;
+
+use crate::IngestMethod;
+
+pub struct Matrix {
+    /// Connection strings, as given to --connection-strings
+    pub connections: Vec<String>,
+    pub methods: Vec<IngestMethod>,
+    pub batch_sizes: Vec<usize>,
+    pub threads: Vec<usize>,
+    pub transactions: Vec<bool>,
+    pub tables: Vec<TableVariant>,
+    /// Session settings applied on every worker connection, each with the values to sweep
+    pub settings: Vec<(String, Vec<String>)>,
+    pub iterations: u32,
+    pub warmup: u32,
+}
+
+pub struct TableVariant {
+    pub table: String,
+    /// File with the CREATE TABLE statement to run when the table doesn't exist
+    pub ddl: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    connections: Option<Vec<String>>,
+    methods: Option<Vec<String>>,
+    batch_sizes: Option<Vec<usize>>,
+    threads: Option<Vec<usize>>,
+    transactions: Option<Vec<bool>>,
+    iterations: Option<u32>,
+    warmup: Option<u32>,
+    tables: Option<Vec<TableFile>>,
+    #[serde(default)]
+    settings: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TableFile {
+    name: String,
+    ddl: Option<PathBuf>,
+}
+
+/// Reads the scenario at `path` over `defaults`. Also returns the file as JSON for the
+/// run metadata, with the passwords in its connection strings masked.
+pub fn load(path: &Path, defaults: Matrix) -> Result<(Matrix, serde_json::Value), Box<dyn Error + Send + Sync>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Can't read scenario {}: {}", path.display(), e))?;
+    let raw: toml::Value = toml::from_str(&content)
+        .map_err(|e| format!("Invalid scenario {}: {}", path.display(), e.message()))?;
+    let file: ScenarioFile = raw
+        .clone()
+        .try_into()
+        .map_err(|e: toml::de::Error| format!("Invalid scenario {}: {}", path.display(), e.message()))?;
+
+    let methods = match file.methods {
+        Some(names) => names
+            .iter()
+            .map(|name| IngestMethod::from_str(name, true).map_err(|_| format!("Unknown method '{}' in scenario", name)))
+            .collect::<Result<_, _>>()?,
+        None => defaults.methods,
+    };
+    // DDL files are relative to the scenario
+    let base = path.parent().unwrap_or(Path::new(""));
+    let tables = match file.tables {
+        Some(tables) => tables
+            .into_iter()
+            .map(|table| TableVariant { table: table.name, ddl: table.ddl.map(|ddl| base.join(ddl)) })
+            .collect(),
+        None => defaults.tables,
+    };
+    let mut settings = defaults.settings;
+    for (name, value) in file.settings {
+        settings.retain(|(existing, _)| *existing != name);
+        let values = match &value {
+            toml::Value::Array(values) => values.iter().map(|value| setting_value(&name, value)).collect::<Result<_, _>>()?,
+            value => vec![setting_value(&name, value)?],
+        };
+        settings.push((name, values));
+    }
+
+    let matrix = Matrix {
+        connections: file.connections.unwrap_or(defaults.connections),
+        methods,
+        batch_sizes: file.batch_sizes.unwrap_or(defaults.batch_sizes),
+        threads: file.threads.unwrap_or(defaults.threads),
+        transactions: file.transactions.unwrap_or(defaults.transactions),
+        tables,
+        settings,
+        iterations: file.iterations.unwrap_or(defaults.iterations),
+        warmup: file.warmup.unwrap_or(defaults.warmup),
+    };
+    let mut json = serde_json::to_value(raw)?;
+    if let Some(serde_json::Value::Array(connections)) = json.get_mut("connections") {
+        for connection in connections {
+            if let Some(s) = connection.as_str() {
+                *connection = crate::report::redact_passwords(s).into();
+            }
+        }
+    }
+    Ok((matrix, json))
+}
+
+/// Parses `--set name=value[,value...]`
+pub fn parse_setting(s: &str) -> Result<(String, Vec<String>), String> {
+    let (name, values) = crate::input::parse_key_value(s)?;
+    let values: Vec<String> = values.split(',').map(|value| value.trim().to_string()).collect();
+    if name.is_empty() || values.iter().any(String::is_empty) {
+        return Err(format!("expected name=value[,value...], got '{}'", s));
+    }
+    Ok((name, values))
+}
+
+/// One `SELECT set_config(...)` for all session `settings` with its parameters, which both
+/// engines run on every worker connection. None without settings.
+pub fn set_config_query(settings: &[(String, String)]) -> Option<(String, Vec<&(dyn ToSql + Sync)>)> {
+    if settings.is_empty() {
+        return None;
+    }
+    let calls: Vec<String> = (0..settings.len())
+        .map(|i| format!("set_config(${}, ${}, false)", 2 * i + 1, 2 * i + 2))
+        .collect();
+    let params = settings
+        .iter()
+        .flat_map(|(name, value)| [name as &(dyn ToSql + Sync), value as &(dyn ToSql + Sync)])
+        .collect();
+    Some((format!("SELECT {}", calls.join(", ")), params))
+}
+
+/// Error for session settings the server refused
+pub fn settings_error(settings: &[(String, String)], e: impl std::fmt::Display) -> String {
+    let settings: Vec<String> = settings.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+    format!("Can't apply {}: {}", settings.join(", "), e)
+}
+
+// Settings may be written as TOML strings, numbers or booleans
+fn setting_value(name: &str, value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(if *b { "on" } else { "off" }.to_string()),
+        _ => Err(format!("Setting {} must be a string, number or boolean", name)),
+    }
+}
+
+impl Matrix {
+    pub fn validate(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let empty = [
+            ("methods", self.methods.is_empty()),
+            ("batch_sizes", self.batch_sizes.is_empty()),
+            ("threads", self.threads.is_empty()),
+            ("transactions", self.transactions.is_empty()),
+            ("tables", self.tables.is_empty()),
+        ];
+        if let Some((key, _)) = empty.iter().find(|(_, empty)| *empty) {
+            return Err(format!("Nothing to benchmark, no {}", key.replace('_', " ")).into());
+        }
+        if self.batch_sizes.contains(&0) || self.threads.contains(&0) {
+            return Err("Batch sizes and thread counts must be at least 1".into());
+        }
+        if self.iterations == 0 {
+            return Err("Iterations must be at least 1".into());
+        }
+        if let Some((name, _)) = self.settings.iter().find(|(_, values)| values.is_empty()) {
+            return Err(format!("No values for setting {}", name).into());
+        }
+        Ok(())
+    }
+
+    /// Every combination of one value per setting
+    pub fn setting_combinations(&self) -> Vec<Vec<(String, String)>> {
+        self.settings.iter().fold(vec![Vec::new()], |combinations, (name, values)| {
+            combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.push((name.clone(), value.clone()));
+                        combination
+                    })
+                })
+                .collect()
+        })
+    }
+}
@@ -0,0 +1,260 @@
+//! Target table description. Every ingest method derives its SQL, COPY column list,
+//! binary COPY types and UNNEST casts from a [`TableSchema`] instead of hard-coding
+//! the `power_generation` layout.
+
+use std::error::Error;
+
+use postgres::types::{Kind, Type};
+use postgres::Client;
+
+pub const DEFAULT_TABLE: &str = "power_generation";
+
+pub const POWER_GENERATION_DDL: &str = "
+    CREATE TABLE IF NOT EXISTS power_generation (
+        generator_id INTEGER,               -- Unique identifier for the generator or energy source
+        timestamp TIMESTAMP WITH TIME ZONE, -- Timestamp of the reading
+        power_output_kw DOUBLE PRECISION,   -- Real-time power output in kilowatts (kW)
+        voltage DOUBLE PRECISION,           -- Voltage in volts (V)
+        current DOUBLE PRECISION,           -- Current in amperes (A)
+        frequency DOUBLE PRECISION,         -- Electrical frequency in hertz (Hz)
+        temperature DOUBLE PRECISION        -- Equipment temperature in degrees Celsius (°C)
+    );
+";
+
+/// What an upsert does with a row whose key is already in the table
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConflictAction {
+    DoNothing,
+    /// Overwrite every column outside the key with the incoming values
+    DoUpdate,
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub data_type: Type,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableSchema {
+    /// The table as PostgreSQL prints its regclass: quoted where needed and
+    /// schema-qualified unless it is on the search path, so it can go straight into SQL
+    pub table: String,
+    /// Bare relation name, without schema or quotes
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+impl TableSchema {
+    /// Runs `ddl` if `table` doesn't exist yet, then resolves `table` like PostgreSQL
+    /// resolves a table name in SQL and reads its insertable columns from `pg_attribute`.
+    pub fn load(client: &mut Client, table: &str, ddl: Option<&str>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if let Some(ddl) = ddl {
+            let exists: bool = client
+                .query_one("SELECT to_regclass($1) IS NOT NULL", &[&table])?
+                .get(0);
+            if !exists {
+                client.batch_execute(ddl)?;
+            }
+        }
+
+        let relation = client
+            .query_opt(
+                "SELECT c.oid, c.oid::regclass::text, c.relname::text
+                 FROM pg_catalog.pg_class c
+                 WHERE c.oid = to_regclass($1)",
+                &[&table],
+            )?
+            .ok_or_else(|| format!("Table {} does not exist", table))?;
+        let (oid, table, name): (u32, String, String) = (relation.get(0), relation.get(1), relation.get(2));
+        let table = table.as_str();
+
+        // Generated and GENERATED ALWAYS AS IDENTITY columns can't be inserted into;
+        // domains are loaded as their base type
+        let rows = client.query(
+            "SELECT a.attname::text, t.oid, t.typname::text
+             FROM pg_catalog.pg_attribute a
+             JOIN pg_catalog.pg_type d ON d.oid = a.atttypid
+             JOIN pg_catalog.pg_type t ON t.oid = CASE d.typtype WHEN 'd' THEN d.typbasetype ELSE d.oid END
+             WHERE a.attrelid = $1
+               AND a.attnum > 0
+               AND NOT a.attisdropped
+               AND a.attgenerated = ''
+               AND a.attidentity <> 'a'
+             ORDER BY a.attnum",
+            &[&oid],
+        )?;
+        if rows.is_empty() {
+            return Err(format!("Table {} has no insertable columns", table).into());
+        }
+
+        let columns = rows
+            .iter()
+            .map(|row| {
+                let name: String = row.get(0);
+                let oid: u32 = row.get(1);
+                let type_name: String = row.get(2);
+                let data_type = Type::from_oid(oid).ok_or_else(|| {
+                    format!("Column {}.{} has unsupported type {}", table, name, type_name)
+                })?;
+                Ok(Column { name, data_type })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+
+        Ok(TableSchema { table: table.to_string(), name, columns })
+    }
+
+    pub fn types(&self) -> Vec<Type> {
+        self.columns.iter().map(|c| c.data_type.clone()).collect()
+    }
+
+    pub fn has_array_columns(&self) -> bool {
+        self.columns.iter().any(|c| matches!(c.data_type.kind(), Kind::Array(_)))
+    }
+
+    pub fn column_list(&self) -> String {
+        self.columns
+            .iter()
+            .map(|c| quote_ident(&c.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// `INSERT ... VALUES` with one placeholder group per row
+    pub fn insert_values_sql(&self, rows: usize) -> String {
+        let width = self.columns.len();
+        let value_strings: Vec<String> = (0..rows)
+            .map(|i| {
+                let placeholders: Vec<String> = (1..=width).map(|c| format!("${}", i * width + c)).collect();
+                format!("({})", placeholders.join(", "))
+            })
+            .collect();
+        format!("INSERT INTO {} ({}) VALUES {}", self.table, self.column_list(), value_strings.join(", "))
+    }
+
+    /// `INSERT ... SELECT * FROM unnest(...)` taking one array parameter per column
+    pub fn unnest_sql(&self) -> String {
+        format!(
+            "INSERT INTO {} ({}) SELECT * FROM unnest({})",
+            self.table,
+            self.column_list(),
+            self.unnest_arrays()
+        )
+    }
+
+    /// `MERGE ... USING unnest(...)` on `key`, taking one array parameter per column: rows
+    /// whose key exists update the other columns, the rest are inserted (PostgreSQL 15+)
+    pub fn merge_sql(&self, key: &[String]) -> String {
+        let on: Vec<String> = key.iter().map(|c| format!("target.{0} = source.{0}", quote_ident(c))).collect();
+        let assignments: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|c| !key.contains(&c.name))
+            .map(|c| format!("{0} = source.{0}", quote_ident(&c.name)))
+            .collect();
+        let matched = if assignments.is_empty() {
+            "DO NOTHING".to_string()
+        } else {
+            format!("UPDATE SET {}", assignments.join(", "))
+        };
+        let values: Vec<String> = self.columns.iter().map(|c| format!("source.{}", quote_ident(&c.name))).collect();
+        format!(
+            "MERGE INTO {0} AS target USING unnest({1}) AS source({2}) ON {3} \
+             WHEN MATCHED THEN {4} WHEN NOT MATCHED THEN INSERT ({2}) VALUES ({5})",
+            self.table,
+            self.unnest_arrays(),
+            self.column_list(),
+            on.join(" AND "),
+            matched,
+            values.join(", ")
+        )
+    }
+
+    /// `CREATE <kind> TABLE` for an empty staging table `name` with the insertable columns,
+    /// after dropping a leftover `name` of an earlier failed run with the same backend pid
+    pub fn create_staging_sql(&self, name: &str, kind: &str) -> String {
+        format!(
+            "DROP TABLE IF EXISTS {1}; CREATE {0} TABLE {1} AS SELECT {2} FROM {3} WITH NO DATA",
+            kind,
+            name,
+            self.column_list(),
+            self.table
+        )
+    }
+
+    /// `INSERT ... SELECT` of the rows of `source`, only one per value of `distinct_key` if given
+    pub fn insert_select_sql(&self, source: &str, distinct_key: Option<&[String]>) -> String {
+        let distinct = match distinct_key {
+            Some(key) => {
+                let key: Vec<String> = key.iter().map(|c| quote_ident(c)).collect();
+                format!("DISTINCT ON ({}) ", key.join(", "))
+            }
+            None => String::new(),
+        };
+        format!(
+            "INSERT INTO {0} ({1}) SELECT {2}{1} FROM {3}",
+            self.table,
+            self.column_list(),
+            distinct,
+            source
+        )
+    }
+
+    // One array parameter per column, cast to the column type
+    fn unnest_arrays(&self) -> String {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("${}::{}[]", i + 1, c.data_type.name()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// `ON CONFLICT` clause to append to an insert, for a unique index on `key`
+    pub fn on_conflict_sql(&self, key: &[String], action: ConflictAction) -> String {
+        let target = key.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ");
+        match action {
+            ConflictAction::DoNothing => format!(" ON CONFLICT ({}) DO NOTHING", target),
+            ConflictAction::DoUpdate => {
+                let assignments: Vec<String> = self
+                    .columns
+                    .iter()
+                    .filter(|c| !key.contains(&c.name))
+                    .map(|c| format!("{0} = EXCLUDED.{0}", quote_ident(&c.name)))
+                    .collect();
+                format!(" ON CONFLICT ({}) DO UPDATE SET {}", target, assignments.join(", "))
+            }
+        }
+    }
+
+    /// Name of the unique constraint that upserts conflict on
+    pub fn conflict_constraint(&self) -> String {
+        format!("{}_pgingester_key", self.name)
+    }
+
+    pub fn copy_sql(&self) -> String {
+        format!("COPY {} ({}) FROM STDIN", self.table, self.column_list())
+    }
+
+    pub fn binary_copy_sql(&self) -> String {
+        format!("COPY {} ({}) FROM STDIN WITH (FORMAT binary)", self.table, self.column_list())
+    }
+
+    /// `COPY ... WITH (FREEZE)`, for a table truncated or created in the same transaction
+    pub fn copy_freeze_sql(&self) -> String {
+        format!("COPY {} ({}) FROM STDIN WITH (FREEZE)", self.table, self.column_list())
+    }
+
+    pub fn binary_copy_freeze_sql(&self) -> String {
+        format!("COPY {} ({}) FROM STDIN WITH (FORMAT binary, FREEZE)", self.table, self.column_list())
+    }
+
+    /// Largest `INSERT ... VALUES` batch that fits in the protocol's i16 parameter count
+    pub fn max_values_batch(&self) -> usize {
+        i16::MAX as usize / self.columns.len()
+    }
+}
+
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,150 @@
+//! Server-side counters sampled around every run, to explain where the time went:
+//! WAL volume from `pg_current_wal_lsn()` and `pg_stat_wal`, buffers written from
+//! `pg_stat_io` (PostgreSQL 16+) or `pg_stat_bgwriter`, and the final table size.
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use postgres::Client;
+
+use crate::schema::TableSchema;
+
+// How long to wait for worker backends to exit and flush their statistics
+const BACKEND_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn server_version_num(client: &mut Client) -> Result<i32, Box<dyn Error + Send + Sync>> {
+    let version: String = client.query_one("SELECT current_setting('server_version_num')", &[])?.get(0);
+    Ok(version.parse()?)
+}
+
+/// Cumulative counters at one point in time
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    wal_lsn: i64,
+    // pg_stat_wal exists from PostgreSQL 14
+    wal_records: Option<i64>,
+    wal_fpi: Option<i64>,
+    buffers_written: i64,
+    // Other client backends connected when the snapshot was taken
+    backends: i64,
+}
+
+/// Server work done during one run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServerMetrics {
+    pub wal_bytes: i64,
+    pub wal_records: Option<i64>,
+    pub wal_fpi: Option<i64>,
+    pub buffers_written: i64,
+    pub table_size: i64,
+}
+
+/// Visibility map summary of the table after a COPY FREEZE run
+#[derive(Debug, Clone, Copy)]
+pub struct FrozenPages {
+    pub all_frozen: i64,
+    pub pages: i64,
+}
+
+fn other_backends(client: &mut Client) -> Result<i64, Box<dyn Error + Send + Sync>> {
+    Ok(client
+        .query_one(
+            "SELECT count(*) FROM pg_stat_activity WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()",
+            &[],
+        )?
+        .get(0))
+}
+
+pub fn snapshot(client: &mut Client, server_version: i32) -> Result<Snapshot, Box<dyn Error + Send + Sync>> {
+    client.execute("SELECT pg_stat_clear_snapshot()", &[])?;
+    let wal_lsn: i64 = client
+        .query_one("SELECT pg_wal_lsn_diff(pg_current_wal_lsn(), '0/0')::int8", &[])?
+        .get(0);
+    let (wal_records, wal_fpi) = if server_version >= 140000 {
+        let row = client.query_one("SELECT wal_records, wal_fpi FROM pg_stat_wal", &[])?;
+        (Some(row.get(0)), Some(row.get(1)))
+    } else {
+        (None, None)
+    };
+    let buffers_written: i64 = if server_version >= 160000 {
+        client.query_one("SELECT coalesce(sum(writes), 0)::int8 FROM pg_stat_io", &[])?.get(0)
+    } else {
+        client
+            .query_one("SELECT buffers_checkpoint + buffers_clean + buffers_backend FROM pg_stat_bgwriter", &[])?
+            .get(0)
+    };
+    Ok(Snapshot {
+        wal_lsn,
+        wal_records,
+        wal_fpi,
+        buffers_written,
+        backends: other_backends(client)?,
+    })
+}
+
+/// Waits for the run's worker backends to go away, since they only flush their
+/// statistics on exit, then compares a new snapshot with `before`.
+pub fn measure(
+    client: &mut Client,
+    server_version: i32,
+    schema: &TableSchema,
+    before: &Snapshot,
+) -> Result<ServerMetrics, Box<dyn Error + Send + Sync>> {
+    let deadline = Instant::now() + BACKEND_EXIT_TIMEOUT;
+    while other_backends(client)? > before.backends && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let after = snapshot(client, server_version)?;
+    let table_size: i64 = client
+        .query_one("SELECT pg_total_relation_size($1::text::regclass)", &[&schema.table])?
+        .get(0);
+    Ok(ServerMetrics {
+        wal_bytes: after.wal_lsn - before.wal_lsn,
+        wal_records: after.wal_records.zip(before.wal_records).map(|(a, b)| a - b),
+        wal_fpi: after.wal_fpi.zip(before.wal_fpi).map(|(a, b)| a - b),
+        buffers_written: after.buffers_written - before.buffers_written,
+        table_size,
+    })
+}
+
+/// All-frozen pages of the table according to its visibility map, and its total number
+/// of pages. None if the `pg_visibility` extension isn't installed in the database.
+pub fn frozen_pages(client: &mut Client, schema: &TableSchema) -> Result<Option<FrozenPages>, Box<dyn Error + Send + Sync>> {
+    let extension_schema = client.query_opt(
+        "SELECT quote_ident(n.nspname)
+         FROM pg_extension e
+         JOIN pg_namespace n ON n.oid = e.extnamespace
+         WHERE e.extname = 'pg_visibility'",
+        &[],
+    )?;
+    let Some(extension_schema) = extension_schema.map(|row| row.get::<_, String>(0)) else {
+        return Ok(None);
+    };
+    let row = client.query_one(
+        &format!(
+            "SELECT all_frozen, pg_relation_size($1::text::regclass) / current_setting('block_size')::int8
+             FROM {}.pg_visibility_map_summary($1::text::regclass)",
+            extension_schema
+        ),
+        &[&schema.table],
+    )?;
+    Ok(Some(FrozenPages { all_frozen: row.get(0), pages: row.get(1) }))
+}
+
+impl ServerMetrics {
+    /// Per-run average
+    pub fn mean(runs: &[ServerMetrics]) -> ServerMetrics {
+        let n = runs.len().max(1) as i64;
+        let mean_of = |f: fn(&ServerMetrics) -> i64| runs.iter().map(f).sum::<i64>() / n;
+        let mean_of_optional = |f: fn(&ServerMetrics) -> Option<i64>| {
+            runs.iter().map(f).sum::<Option<i64>>().map(|total| total / n)
+        };
+        ServerMetrics {
+            wal_bytes: mean_of(|m| m.wal_bytes),
+            wal_records: mean_of_optional(|m| m.wal_records),
+            wal_fpi: mean_of_optional(|m| m.wal_fpi),
+            buffers_written: mean_of(|m| m.buffers_written),
+            table_size: mean_of(|m| m.table_size),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Summary statistics over the iterations of one benchmark, and batch latency histograms.
+
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    /// Sample standard deviation; zero for a single sample
+    pub stddev: f64,
+}
+
+impl Summary {
+    pub fn of(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Summary::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let n = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let median = if n.is_multiple_of(2) { (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0 } else { sorted[n / 2] };
+        let stddev = if n > 1 {
+            (sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        Summary {
+            min: sorted[0],
+            max: sorted[n - 1],
+            mean,
+            median,
+            p95: percentile(&sorted, 0.95),
+            stddev,
+        }
+    }
+}
+
+// Nearest-rank percentile of an ascending slice
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Per-batch latencies of one or more workers, in microseconds
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram(Histogram<u64>);
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        // 1µs to one hour at three significant digits; longer batches are clamped
+        LatencyHistogram(Histogram::new_with_bounds(1, 3_600_000_000, 3).expect("valid histogram bounds"))
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.0.saturating_record(latency.as_micros().min(u64::MAX as u128) as u64);
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        self.0.add(&other.0).expect("histograms share the same bounds");
+    }
+
+    /// Latency at `percentile` (0-100)
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_micros(self.0.value_at_percentile(percentile))
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.0.max())
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,277 @@
+//! Built-in synthetic data generator, used instead of a CSV file with `--synthetic N`.
+//!
+//! Rows follow the target schema: the first integer column is the series (generator) id,
+//! time columns advance by `--timestamp-spacing-ms` per series, and the remaining
+//! columns are drawn from the configured value distribution. The same seed always
+//! produces the same rows.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::{Args, ValueEnum};
+use postgres::types::{Kind, Type};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::row::{Row, Value};
+use crate::schema::TableSchema;
+
+// Out-of-order rows are held back for up to this many timestamp intervals
+const OUT_OF_ORDER_MAX_LAG: u64 = 100;
+
+#[derive(Args, Debug, Clone)]
+#[command(next_help_heading = "Synthetic data")]
+pub struct SyntheticArgs {
+    /// Generate this many rows in memory instead of reading --input-file
+    #[arg(long)]
+    pub synthetic: Option<usize>,
+
+    /// Seed for the synthetic data generator
+    #[arg(long, default_value = "42")]
+    pub seed: u64,
+
+    /// Number of distinct generator ids
+    #[arg(long, default_value = "100")]
+    pub generator_ids: usize,
+
+    /// First timestamp (RFC3339)
+    #[arg(long, default_value = "2024-01-01T00:00:00Z")]
+    pub start_time: DateTime<Utc>,
+
+    /// Time between two readings of the same generator, in milliseconds
+    #[arg(long, default_value = "1000")]
+    pub timestamp_spacing_ms: i64,
+
+    /// Fraction of rows (0.0-1.0) that arrive late, after rows with newer timestamps
+    #[arg(long, default_value = "0.0")]
+    pub out_of_order: f64,
+
+    /// Distribution of the generated measurement values
+    #[arg(long, value_enum, default_value = "uniform")]
+    pub distribution: Distribution,
+
+    /// Lower bound of the generated values (mean - 3 sd for normal)
+    #[arg(long, default_value = "0.0", allow_negative_numbers = true)]
+    pub value_min: f64,
+
+    /// Upper bound of the generated values (mean + 3 sd for normal)
+    #[arg(long, default_value = "100.0", allow_negative_numbers = true)]
+    pub value_max: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Distribution {
+    /// Uniform between --value-min and --value-max
+    Uniform,
+    /// Normal, centered between --value-min and --value-max
+    Normal,
+    /// Random walk per generator and column, starting uniformly in range
+    Walk,
+}
+
+/// SplitMix64; small, fast and stable across releases, unlike the `rand` generators
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    // Standard normal via Box-Muller
+    fn next_normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// Endless iterator of synthetic rows for one schema
+pub struct Generator {
+    args: SyntheticArgs,
+    types: Vec<Type>,
+    width: usize,
+    id_column: Option<usize>,
+    rng: SplitMix64,
+    // Current random-walk position per generator id and column
+    walks: Vec<f64>,
+    row: u64,
+    // Rows held back by --out-of-order, keyed by the generated row count they are due at
+    late: BTreeMap<(u64, u64), Row>,
+}
+
+impl Generator {
+    pub fn new(schema: &TableSchema, args: &SyntheticArgs) -> Self {
+        let types = schema.types();
+        let id_column = types.iter().position(|t| matches!(*t, Type::INT2 | Type::INT4 | Type::INT8));
+        let series = args.generator_ids.max(1);
+        let mut rng = SplitMix64(args.seed);
+        let walks = (0..series * types.len())
+            .map(|_| args.value_min + rng.next_f64() * (args.value_max - args.value_min))
+            .collect();
+        Generator {
+            args: args.clone(),
+            width: types.len(),
+            types,
+            id_column,
+            rng,
+            walks,
+            row: 0,
+            late: BTreeMap::new(),
+        }
+    }
+
+    fn measurement(&mut self, series: usize, column: usize) -> f64 {
+        let (min, max) = (self.args.value_min, self.args.value_max);
+        match self.args.distribution {
+            Distribution::Uniform => min + self.rng.next_f64() * (max - min),
+            Distribution::Normal => (min + max) / 2.0 + self.rng.next_normal() * (max - min) / 6.0,
+            Distribution::Walk => {
+                let step = self.rng.next_normal() * (max - min) / 100.0;
+                let position = &mut self.walks[series * self.width + column];
+                *position += step;
+                *position
+            }
+        }
+    }
+
+    fn value(&mut self, ty: &Type, series: usize, column: usize, timestamp: DateTime<Utc>) -> Value {
+        if let Kind::Array(member) = ty.kind() {
+            let elements: Vec<Value> = (0..3).map(|_| self.value(member, series, column, timestamp)).collect();
+            return Value::Array(elements.into());
+        }
+        if Some(column) == self.id_column {
+            let id = series as i64 + 1;
+            return match *ty {
+                Type::INT2 => Value::Int2(id as i16),
+                Type::INT4 => Value::Int4(id as i32),
+                _ => Value::Int8(id),
+            };
+        }
+        match *ty {
+            Type::BOOL => Value::Bool(self.rng.below(2) == 1),
+            Type::INT2 => Value::Int2(self.rng.below(1000) as i16),
+            Type::INT4 => Value::Int4(self.rng.below(1_000_000) as i32),
+            Type::INT8 => Value::Int8(self.rng.below(1_000_000_000) as i64),
+            Type::FLOAT4 => Value::Float4(self.measurement(series, column) as f32),
+            Type::NUMERIC => Value::Numeric(
+                Decimal::from_f64_retain(self.measurement(series, column))
+                    .map(|d| d.round_dp(2))
+                    .unwrap_or_default(),
+            ),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+                Value::Text(format!("g{}-{:08x}", series + 1, self.rng.next_u64() as u32).into())
+            }
+            Type::TIMESTAMPTZ => Value::TimestampTz(timestamp),
+            Type::TIMESTAMP => Value::Timestamp(timestamp.naive_utc()),
+            Type::DATE => Value::Date(timestamp.date_naive()),
+            Type::JSON | Type::JSONB => Value::Json(Box::new(serde_json::json!({
+                "generator": series + 1,
+                "reading": self.measurement(series, column),
+            }))),
+            Type::UUID => Value::Uuid(Uuid::from_u64_pair(self.rng.next_u64(), self.rng.next_u64())),
+            Type::BYTEA => Value::Bytea(self.rng.next_u64().to_be_bytes().into()),
+            _ => Value::Float8(self.measurement(series, column)),
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Row;
+
+    // Every (series, tick) is generated exactly once; out-of-order rows keep their
+    // timestamp and are emitted later instead, so the keys stay unique
+    fn next(&mut self) -> Option<Row> {
+        let series_count = self.args.generator_ids.max(1) as u64;
+        loop {
+            if let Some(entry) = self.late.first_entry() {
+                if entry.key().0 <= self.row {
+                    return Some(entry.remove());
+                }
+            }
+
+            let series = (self.row % series_count) as usize;
+            let tick = self.row / series_count;
+            let lag = if self.args.out_of_order > 0.0 && self.rng.next_f64() < self.args.out_of_order {
+                Some(1 + self.rng.below(OUT_OF_ORDER_MAX_LAG))
+            } else {
+                None
+            };
+            let timestamp = self.args.start_time
+                + TimeDelta::milliseconds(self.args.timestamp_spacing_ms.saturating_mul(tick as i64));
+            self.row += 1;
+
+            let types = std::mem::take(&mut self.types);
+            let row: Row = types
+                .iter()
+                .enumerate()
+                .map(|(column, ty)| self.value(ty, series, column, timestamp))
+                .collect();
+            self.types = types;
+
+            match lag {
+                Some(lag) => {
+                    self.late.insert((self.row + lag * series_count, self.row), row);
+                }
+                None => return Some(row),
+            }
+        }
+    }
+}
+
+pub fn generate(schema: &TableSchema, args: &SyntheticArgs, rows: usize) -> Vec<Row> {
+    Generator::new(schema, args).take(rows).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::schema::Column;
+
+    #[test]
+    fn out_of_order_rows_keep_unique_keys() {
+        let schema = TableSchema {
+            table: "t".to_string(),
+            name: "t".to_string(),
+            columns: vec![
+                Column { name: "id".to_string(), data_type: Type::INT4 },
+                Column { name: "ts".to_string(), data_type: Type::TIMESTAMPTZ },
+            ],
+        };
+        let args = SyntheticArgs {
+            synthetic: None,
+            seed: 7,
+            generator_ids: 10,
+            start_time: DateTime::UNIX_EPOCH,
+            timestamp_spacing_ms: 1000,
+            out_of_order: 0.3,
+            distribution: Distribution::Uniform,
+            value_min: 0.0,
+            value_max: 1.0,
+        };
+        let rows = generate(&schema, &args, 10_000);
+        let keys: HashSet<String> = rows.iter().map(|r| format!("{:?}", r)).collect();
+        assert_eq!(keys.len(), rows.len());
+
+        let timestamps: Vec<&Value> = rows.iter().map(|r| &r[1]).collect();
+        assert!(timestamps.windows(2).any(|w| matches!((w[0], w[1]), (Value::TimestampTz(a), Value::TimestampTz(b)) if a > b)));
+
+        // Everything is emitted eventually, even when every row is late
+        let all_late = SyntheticArgs { out_of_order: 1.0, ..args };
+        assert_eq!(generate(&schema, &all_late, 1000).len(), 1000);
+    }
+}
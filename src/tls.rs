@@ -0,0 +1,238 @@
+//! TLS for benchmark connections, configured like libpq: `sslmode` (disable, prefer,
+//! require, verify-ca, verify-full), `sslrootcert`, `sslcert` and `sslkey`, taken from
+//! the connection string or the matching command-line flags.
+//!
+//! tokio-postgres only understands `sslmode` up to `require` and none of the certificate
+//! parameters, so they are taken out of the connection parameters and applied to the
+//! OpenSSL connector instead. `sslmode=disable` connects with `NoTls`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::store::X509StoreBuilder;
+use postgres::{Client, NoTls};
+use postgres_openssl::MakeTlsConnector;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Args, Debug, Clone)]
+pub struct TlsArgs {
+    /// TLS mode for connection strings without sslmode [default: prefer]
+    #[arg(long, value_enum)]
+    sslmode: Option<SslMode>,
+
+    /// CA certificates to verify servers with, or "system" for the system store
+    #[arg(long)]
+    sslrootcert: Option<PathBuf>,
+
+    /// Client certificate to authenticate with
+    #[arg(long)]
+    sslcert: Option<PathBuf>,
+
+    /// Private key of --sslcert
+    #[arg(long)]
+    sslkey: Option<PathBuf>,
+}
+
+impl TlsArgs {
+    /// The flags that were given, as connection parameters
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let path = |path: &Option<PathBuf>| path.as_ref().map(|path| path.to_string_lossy().into_owned());
+        [
+            ("sslmode", self.sslmode.map(|mode| mode.name().to_string())),
+            ("sslrootcert", path(&self.sslrootcert)),
+            ("sslcert", path(&self.sslcert)),
+            ("sslkey", path(&self.sslkey)),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SslMode {
+    /// Plain connections without TLS
+    Disable,
+    /// TLS if the server supports it, without verifying the certificate
+    Prefer,
+    /// Always TLS, verifying the CA only if sslrootcert is given or ~/.postgresql/root.crt exists
+    Require,
+    /// Always TLS, with the certificate verified against sslrootcert
+    VerifyCa,
+    /// Like verify-ca, and the certificate must also match the host name
+    VerifyFull,
+}
+
+impl SslMode {
+    fn parse(value: &str) -> Result<Self, BoxError> {
+        SslMode::from_str(value, false).map_err(|_| format!("invalid sslmode '{}'", value).into())
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SslMode::Disable => "disable",
+            SslMode::Prefer => "prefer",
+            SslMode::Require => "require",
+            SslMode::VerifyCa => "verify-ca",
+            SslMode::VerifyFull => "verify-full",
+        }
+    }
+
+    /// What tokio-postgres gets; certificate checks happen in the connector
+    pub fn negotiated(self) -> &'static str {
+        match self {
+            SslMode::Disable => "disable",
+            SslMode::Prefer => "prefer",
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => "require",
+        }
+    }
+}
+
+/// TLS settings of one connection
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub mode: SslMode,
+    /// CA certificates to verify the server with; `system` for the system store
+    pub rootcert: Option<PathBuf>,
+    /// Client certificate and key
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    /// Removes the TLS parameters from resolved connection parameters
+    pub fn take_from(params: &mut BTreeMap<String, String>) -> Result<Self, BoxError> {
+        let mode = match params.remove("sslmode") {
+            Some(mode) => SslMode::parse(&mode)?,
+            None => SslMode::Prefer,
+        };
+        Ok(TlsOptions {
+            mode,
+            rootcert: params.remove("sslrootcert").map(PathBuf::from),
+            cert: params.remove("sslcert").map(PathBuf::from),
+            key: params.remove("sslkey").map(PathBuf::from),
+        })
+    }
+}
+
+/// Whether the session of `client` is encrypted
+pub fn is_encrypted(client: &mut Client) -> Result<bool, BoxError> {
+    Ok(client.query_one("SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()", &[])?.get(0))
+}
+
+/// Opens connections with or without TLS
+#[derive(Clone)]
+pub enum Connector {
+    NoTls,
+    Tls(MakeTlsConnector),
+}
+
+impl std::fmt::Debug for Connector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Connector::NoTls => "NoTls",
+            Connector::Tls(_) => "Tls",
+        })
+    }
+}
+
+impl Connector {
+    pub fn new(options: &TlsOptions) -> Result<Self, BoxError> {
+        if options.mode == SslMode::Disable {
+            return Ok(Connector::NoTls);
+        }
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+
+        // Like libpq, fall back to the files in ~/.postgresql
+        let rootcert = options.rootcert.clone().or_else(|| default_file("root.crt"));
+        let verify = match options.mode {
+            SslMode::VerifyCa | SslMode::VerifyFull => true,
+            // Like libpq, require verifies the CA when there is a root certificate,
+            // including the default ~/.postgresql/root.crt
+            SslMode::Require => rootcert.is_some(),
+            SslMode::Prefer | SslMode::Disable => false,
+        };
+        match &rootcert {
+            // The system store is the builder's default
+            Some(path) if path.as_os_str() == "system" => {}
+            // Only the root certificate is trusted then, not the system store as well
+            Some(path) if verify => {
+                builder.set_cert_store(X509StoreBuilder::new()?.build());
+                builder
+                    .set_ca_file(path)
+                    .map_err(|e| format!("Can't load sslrootcert {}: {}", path.display(), e))?
+            }
+            None if verify && options.mode != SslMode::Require => {
+                return Err("sslmode verify-ca and verify-full need sslrootcert, ~/.postgresql/root.crt or sslrootcert=system".into());
+            }
+            _ => {}
+        }
+        builder.set_verify(if verify { SslVerifyMode::PEER } else { SslVerifyMode::NONE });
+
+        let cert = options.cert.clone().or_else(|| default_file("postgresql.crt"));
+        if let Some(cert) = cert {
+            let key = options
+                .key
+                .clone()
+                .or_else(|| default_file("postgresql.key"))
+                .ok_or_else(|| format!("sslcert {} given without sslkey", cert.display()))?;
+            builder
+                .set_certificate_chain_file(&cert)
+                .map_err(|e| format!("Can't load sslcert {}: {}", cert.display(), e))?;
+            builder
+                .set_private_key_file(&key, SslFiletype::PEM)
+                .map_err(|e| format!("Can't load sslkey {}: {}", key.display(), e))?;
+        }
+
+        let mut connector = MakeTlsConnector::new(builder.build());
+        if options.mode != SslMode::VerifyFull {
+            connector.set_callback(|config, _| {
+                config.set_verify_hostname(false);
+                Ok(())
+            });
+        }
+        Ok(Connector::Tls(connector))
+    }
+
+    pub fn connect(&self, connection_string: &str) -> Result<Client, postgres::Error> {
+        match self {
+            Connector::NoTls => Client::connect(connection_string, NoTls),
+            Connector::Tls(tls) => Client::connect(connection_string, tls.clone()),
+        }
+    }
+
+    /// Connects a tokio-postgres client, driving the connection on a spawned task
+    pub async fn connect_async(&self, connection_string: &str) -> Result<tokio_postgres::Client, tokio_postgres::Error> {
+        fn drive<F>(connection: F)
+        where
+            F: std::future::Future<Output = Result<(), tokio_postgres::Error>> + Send + 'static,
+        {
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    eprintln!("Connection error: {}", e);
+                }
+            });
+        }
+        match self {
+            Connector::NoTls => {
+                let (client, connection) = tokio_postgres::connect(connection_string, NoTls).await?;
+                drive(connection);
+                Ok(client)
+            }
+            Connector::Tls(tls) => {
+                let (client, connection) = tokio_postgres::connect(connection_string, tls.clone()).await?;
+                drive(connection);
+                Ok(client)
+            }
+        }
+    }
+}
+
+fn default_file(name: &str) -> Option<PathBuf> {
+    let path = Path::new(&std::env::var_os("HOME")?).join(".postgresql").join(name);
+    path.exists().then_some(path)
+}